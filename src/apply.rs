@@ -0,0 +1,257 @@
+use csv;
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+use records_reader;
+use pipeline::RecordRule;
+use errors::{Result, ResultExt};
+
+/// What happened to the rules while applying them
+#[derive(Debug, Default)]
+pub struct ApplyReport {
+    pub nb_applied: usize,
+    /// rules whose old_name does not match the current name of the record anymore
+    pub drifted: Vec<(RecordRule, String)>,
+    /// rules whose record (or column) was not found in input
+    pub stale: Vec<RecordRule>,
+    /// rules targeting the same record and column with different new names
+    /// (all of them, none being applied)
+    pub conflicting: Vec<RecordRule>,
+}
+
+/// read a rules file, rules without column (produced by older versions)
+/// apply to the default column
+pub fn read_rules(path: &Path, default_column: &str) -> Result<Vec<RecordRule>> {
+    let mut rdr = csv::Reader::from_path(path).chain_err(|| "Could not open rules file")?;
+    let mut rules = vec![];
+    for res_rule in rdr.deserialize() {
+        let mut rule: RecordRule =
+            res_rule.chain_err(|| format!("error at csv line decoding: {}", path.display()))?;
+        if rule.column.is_empty() {
+            rule.column = default_column.to_string();
+        }
+        rules.push(rule);
+    }
+    Ok(rules)
+}
+
+/// rewrite the csv using the rules matching its records' id,
+/// if check_old_name is set, rules whose old_name does not match the record are not applied
+pub fn apply_rules<R: io::Read, W: io::Write>(
+    rdr: &mut csv::Reader<R>,
+    mut wtr: Option<&mut csv::Writer<W>>,
+    heading_id: &str,
+    rules: Vec<RecordRule>,
+    check_old_name: bool,
+) -> Result<ApplyReport> {
+    let mut report = ApplyReport::default();
+
+    let mut rules_per_key: BTreeMap<_, Vec<RecordRule>> = BTreeMap::new();
+    for rule in rules {
+        let key = (rule.id.clone(), rule.column.clone());
+        rules_per_key.entry(key).or_insert_with(Vec::new).push(rule);
+    }
+    // all the rules of a key are conflicting if their new names differ
+    let mut rules_map = BTreeMap::new();
+    for (key, mut rules) in rules_per_key {
+        if rules.iter().any(|r| r.new_name != rules[0].new_name) {
+            report.conflicting.extend(rules);
+        } else {
+            rules_map.insert(key, rules.swap_remove(0));
+        }
+    }
+
+    let headers = rdr.headers()
+        .chain_err(|| "Can't find headers in input file")?
+        .clone();
+    // rules whose column (or the id column) is not in input can't match any record
+    let has_id = records_reader::column_position(&headers, heading_id).is_ok();
+    let (mut rules_map, unmatched): (BTreeMap<_, _>, BTreeMap<_, _>) = rules_map
+        .into_iter()
+        .partition(|(key, _)| {
+            has_id && records_reader::column_position(&headers, &key.1).is_ok()
+        });
+    if has_id {
+        let mut missing_columns: Vec<&str> = unmatched.keys().map(|k| k.1.as_str()).collect();
+        missing_columns.sort();
+        missing_columns.dedup();
+        for c in missing_columns {
+            warn!("Column {} not found in input, its rules can't be applied", c);
+        }
+    } else {
+        warn!("Column {} not found in input, no rule can be applied", heading_id);
+    }
+    report.stale.extend(unmatched.into_values());
+
+    if !has_id {
+        if let Some(w) = wtr {
+            w.write_record(&headers)
+                .chain_err(|| "Could not write header of output file")?;
+            for rec in rdr.records() {
+                let rec = rec.chain_err(|| "error at csv line decoding")?;
+                w.write_record(&rec)
+                    .chain_err(|| "Could not write into output file")?;
+            }
+            w.flush().chain_err(|| "Could not write into output file")?;
+        }
+        return Ok(report);
+    }
+
+    let mut columns: Vec<String> = rules_map.keys().map(|k| k.1.clone()).collect();
+    columns.sort();
+    columns.dedup();
+    let (records, headers) = records_reader::new_record_iter(rdr, heading_id, &columns)?;
+    if let Some(ref mut w) = wtr {
+        w.write_record(&headers)
+            .chain_err(|| "Could not write header of output file")?;
+    }
+
+    for res_rec in records {
+        let mut rec = res_rec.chain_err(|| "error at csv line decoding")?;
+        for (column, name) in &rec.names {
+            if let Some(rule) = rules_map.remove(&(rec.id.clone(), column.clone())) {
+                if check_old_name && &rule.old_name != name {
+                    report.drifted.push((rule, name.clone()));
+                } else {
+                    let pos = records_reader::column_position(&headers, column)?;
+                    rec.raw[pos] = rule.new_name;
+                    report.nb_applied += 1;
+                }
+            }
+        }
+
+        if let Some(ref mut w) = wtr {
+            w.write_record(&rec.raw)
+                .chain_err(|| "Could not write into output file")?;
+        }
+    }
+    report.stale.extend(rules_map.into_values());
+    if let Some(w) = wtr {
+        w.flush().chain_err(|| "Could not write into output file")?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use csv;
+    use pipeline::RecordRule;
+    use super::{apply_rules, ApplyReport};
+
+    const INPUT: &str = "\
+stop_id,stop_name,stop_desc
+1,rue de la gare,gare
+2,place du marché,marché
+3,avenue foch,foch
+";
+
+    fn rule(id: &str, column: &str, old_name: &str, new_name: &str) -> RecordRule {
+        RecordRule {
+            id: id.to_string(),
+            column: column.to_string(),
+            old_name: old_name.to_string(),
+            new_name: new_name.to_string(),
+            debug: String::new(),
+            trace: String::new(),
+        }
+    }
+
+    // the report and the output of the rules applied to the input
+    fn apply(input: &str, rules: Vec<RecordRule>, check_old_name: bool) -> (ApplyReport, String) {
+        let mut rdr = csv::Reader::from_reader(input.as_bytes());
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        let report = apply_rules(&mut rdr, Some(&mut wtr), "stop_id", rules, check_old_name)
+            .unwrap();
+        let output = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        (report, output)
+    }
+
+    fn ids(rules: &[RecordRule]) -> Vec<(&str, &str)> {
+        rules.iter().map(|r| (r.id.as_str(), r.column.as_str())).collect()
+    }
+
+    #[test]
+    fn applied() {
+        let rules = vec![
+            rule("1", "stop_name", "rue de la gare", "Rue de la Gare"),
+            rule("3", "stop_desc", "foch", "Foch"),
+            // the same rule twice is not a conflict
+            rule("3", "stop_name", "avenue foch", "Avenue Foch"),
+            rule("3", "stop_name", "avenue foch", "Avenue Foch"),
+        ];
+        let (report, output) = apply(INPUT, rules, false);
+        assert_eq!(report.nb_applied, 3);
+        assert!(report.drifted.is_empty());
+        assert!(report.stale.is_empty());
+        assert!(report.conflicting.is_empty());
+        assert_eq!(
+            output,
+            "\
+stop_id,stop_name,stop_desc
+1,Rue de la Gare,gare
+2,place du marché,marché
+3,Avenue Foch,Foch
+"
+        );
+    }
+
+    #[test]
+    fn conflicting() {
+        let rules = vec![
+            rule("1", "stop_name", "rue de la gare", "Rue de la Gare"),
+            rule("1", "stop_name", "rue de la gare", "Rue de la gare"),
+            rule("2", "stop_name", "place du marché", "Place du Marché"),
+        ];
+        let (report, output) = apply(INPUT, rules, false);
+        assert_eq!(report.nb_applied, 1);
+        assert_eq!(ids(&report.conflicting), vec![("1", "stop_name"), ("1", "stop_name")]);
+        assert!(output.contains("\n1,rue de la gare,gare\n"));
+        assert!(output.contains("\n2,Place du Marché,marché\n"));
+    }
+
+    #[test]
+    fn stale() {
+        let rules = vec![
+            rule("4", "stop_name", "rue pasteur", "Rue Pasteur"),
+            rule("1", "stop_code", "gare", "GARE"),
+            rule("2", "stop_name", "place du marché", "Place du Marché"),
+        ];
+        let (report, output) = apply(INPUT, rules, false);
+        assert_eq!(report.nb_applied, 1);
+        let mut stale = ids(&report.stale);
+        stale.sort();
+        assert_eq!(stale, vec![("1", "stop_code"), ("4", "stop_name")]);
+        assert!(output.contains("\n2,Place du Marché,marché\n"));
+
+        // without id column, every rule is stale and the input is copied
+        let input = INPUT.replacen("stop_id", "id", 1);
+        let rules = vec![rule("2", "stop_name", "place du marché", "Place du Marché")];
+        let (report, output) = apply(&input, rules, false);
+        assert_eq!(report.nb_applied, 0);
+        assert_eq!(ids(&report.stale), vec![("2", "stop_name")]);
+        assert_eq!(output, input);
+    }
+
+    #[test]
+    fn drifted() {
+        let rules = || {
+            vec![
+                rule("1", "stop_name", "rue de la gare", "Rue de la Gare"),
+                rule("2", "stop_name", "place du marche", "Place du Marché"),
+            ]
+        };
+        let (report, output) = apply(INPUT, rules(), true);
+        assert_eq!(report.nb_applied, 1);
+        assert_eq!(report.drifted.len(), 1);
+        assert_eq!(report.drifted[0].0.id, "2");
+        assert_eq!(report.drifted[0].1, "place du marché");
+        assert!(output.contains("\n1,Rue de la Gare,gare\n"));
+        assert!(output.contains("\n2,place du marché,marché\n"));
+
+        // without the check, the rule is applied anyway
+        let (report, output) = apply(INPUT, rules(), false);
+        assert_eq!(report.nb_applied, 2);
+        assert!(report.drifted.is_empty());
+        assert!(output.contains("\n2,Place du Marché,marché\n"));
+    }
+}
@@ -1,13 +1,64 @@
+use std::collections::HashSet;
 use std::fs::File;
-use worker::{self, bano_reader, ispell_wrapper, regex_processor as rp};
+use worker::{self, accent_restore, bano_reader, ispell_wrapper, overrides, regex_processor as rp,
+             spell_backend};
+use worker::spell_cache::SharedCaches;
 use errors::{Result, ResultExt};
-use serde_yaml;
-use std::path::Path;
+use serde::de::{Deserialize, Deserializer, Error};
+use serde_yaml::{self, Value};
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_COLUMN: &str = "stop_name";
 
 // define config file structure
-#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Deserialize)]
 struct ProcessSequence {
-    processes: Vec<NameProcessor>,
+    /// overrides file (ex: written by review) loaded first in every sequence
+    overrides: Option<String>,
+    #[serde(default)]
+    processes: Vec<LabeledProcessor>,
+    #[serde(default)]
+    columns: Vec<ColumnSequence>,
+}
+
+// a column to be processed, with its own processing sequence if provided
+#[derive(Debug, PartialEq, Deserialize)]
+struct ColumnSequence {
+    name: String,
+    processes: Option<Vec<LabeledProcessor>>,
+}
+
+// a processor with an optional label, given next to the processor:
+//   - label: expand saint
+//     RegexReplace: ...
+// (a processor without parameter is then written `SnakeCase: ~`)
+#[derive(Debug, PartialEq)]
+struct LabeledProcessor {
+    label: Option<String>,
+    processor: NameProcessor,
+}
+impl<'de> Deserialize<'de> for LabeledProcessor {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> ::std::result::Result<Self, D::Error> {
+        let mut value = Value::deserialize(deserializer)?;
+        let mut label = None;
+        if let Value::Mapping(ref mut m) = value {
+            if let Some(l) = m.remove(&Value::String("label".to_string())) {
+                label = Some(serde_yaml::from_value(l).map_err(D::Error::custom)?);
+            }
+        }
+        let unit_variant = match value {
+            Value::Mapping(ref m) if m.len() == 1 => match m.iter().next() {
+                Some((Value::String(k), Value::Null)) => Some(k.clone()),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(k) = unit_variant {
+            value = Value::String(k);
+        }
+        let processor = serde_yaml::from_value(value).map_err(D::Error::custom)?;
+        Ok(LabeledProcessor { label, processor })
+    }
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -19,7 +70,9 @@ enum NameProcessor {
     UppercaseWord(FixedcaseWord),
     RegexReplace(RegexReplace),
     IspellCheck(IspellCheck),
+    AccentRestore(AccentRestore),
     LogSuspicious(LogSuspicious),
+    Overrides(Overrides),
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
@@ -40,47 +93,306 @@ struct RegexReplace {
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 struct IspellCheck {
+    #[serde(default)]
+    backend: spell_backend::BackendKind,
     dictionnary: String,
-    bano_files: Vec<String>,
+    #[serde(default)]
+    bano_files: Vec<CorpusSource>,
+    added_words: Option<String>,
+    word_choices: Option<String>,
+    cache_dir: Option<String>,
+    #[serde(default)]
+    thresholds: bano_reader::Thresholds,
+    /// csv file of the decisions about the words of bano_files
+    candidates: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct AccentRestore {
+    #[serde(default)]
+    dictionaries: Vec<String>,
+    #[serde(default)]
+    bano_files: Vec<CorpusSource>,
+    /// columns of the input whose names are added to the corpus
+    #[serde(default)]
+    input_corpus: Vec<String>,
+    word_choices: Option<String>,
+    #[serde(default)]
+    thresholds: bano_reader::Thresholds,
+}
+
+// a corpus file, given by its path if in BANO format
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+enum CorpusSource {
+    Bano(String),
+    Format(bano_reader::CorpusFile),
+}
+
+fn corpus_files(sources: &[CorpusSource]) -> Vec<bano_reader::CorpusFile> {
+    sources
+        .iter()
+        .map(|s| match *s {
+            CorpusSource::Bano(ref f) => bano_reader::CorpusFile::bano(f),
+            CorpusSource::Format(ref c) => c.clone(),
+        })
+        .collect()
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 struct LogSuspicious {
     regex: String,
+    #[serde(default)]
+    severity: rp::Severity,
 }
 
-pub fn read_conf(conf_file: &str) -> Result<Vec<worker::Processor>> {
-    use self::NameProcessor::*;
-    use worker::Processor as WP;
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Overrides {
+    file: String,
+}
 
+fn read_sequence(conf_file: &str) -> Result<ProcessSequence> {
     let conf_rdr = File::open(conf_file).chain_err(|| "Could not open config file")?;
 
-    let sequence: ProcessSequence =
-        serde_yaml::from_reader(conf_rdr).chain_err(|| "Problem while reading config file")?;
+    serde_yaml::from_reader(conf_rdr).chain_err(|| "Problem while reading config file")
+}
 
-    sequence
-        .processes
-        .into_iter()
-        .map(|a| match a {
-            LowercaseWord(lcw) => rp::FixedcaseProcessor::new(&lcw.words, rp::CaseSpecifier::Lower)
-                .chain_err(|| "Could not create LowercaseWord manager")
-                .map(WP::Fixedcase),
-            UppercaseWord(ucw) => rp::FixedcaseProcessor::new(&ucw.words, rp::CaseSpecifier::Upper)
-                .chain_err(|| "Could not create UppercaseWord manager")
-                .map(WP::Fixedcase),
-            IspellCheck(i) => {
-                // the conf_file is already valid, thus this can't fail
-                let conf_path = Path::new(conf_file).parent().unwrap();
-                let mut ispell = ispell_wrapper::SpellCheck::new(&i.dictionnary)
-                    .chain_err(|| "Could not create ispell manager")?;
-                bano_reader::populate_dict_from_files(&i.bano_files, &mut ispell, conf_path)?;
-                Ok(WP::Ispell(ispell))
+/// read the default processing sequence,
+/// its processors using the caches shared with other sequences
+pub fn read_conf(conf_file: &str, caches: &SharedCaches) -> Result<Vec<worker::Step>> {
+    let sequence = read_sequence(conf_file)?;
+    let processes = with_overrides(sequence.overrides.as_ref(), sequence.processes, conf_file);
+    make_processors(processes, conf_file, caches)
+}
+
+/// read the path of the overrides file given in config if any
+/// (relative to the current directory)
+pub fn read_overrides_path(conf_file: &str) -> Result<Option<PathBuf>> {
+    let sequence = read_sequence(conf_file)?;
+    // the conf_file is already valid, thus this can't fail
+    let conf_path = Path::new(conf_file).parent().unwrap();
+    Ok(sequence.overrides.map(|f| conf_path.join(f)))
+}
+
+// the processes preceded by an Overrides of the overrides file of config if it exists
+fn with_overrides(
+    overrides: Option<&String>,
+    mut processes: Vec<LabeledProcessor>,
+    conf_file: &str,
+) -> Vec<LabeledProcessor> {
+    if let Some(f) = overrides {
+        // the conf_file is already valid, thus this can't fail
+        let conf_path = Path::new(conf_file).parent().unwrap();
+        if conf_path.join(f).exists() {
+            let processor = NameProcessor::Overrides(Overrides { file: f.clone() });
+            processes.insert(0, LabeledProcessor { label: None, processor });
+        } else {
+            info!("Overrides file {} not found, no decision loaded", f);
+        }
+    }
+    processes
+}
+
+/// read the names of the columns listed in config
+pub fn read_listed_columns(conf_file: &str) -> Result<Vec<String>> {
+    let sequence = read_sequence(conf_file)?;
+    Ok(sequence.columns.into_iter().map(|c| c.name).collect())
+}
+
+/// read the columns of the input whose names are used as corpus by some processor
+pub fn read_input_corpus_columns(conf_file: &str) -> Result<Vec<String>> {
+    let sequence = read_sequence(conf_file)?;
+    let mut columns = vec![];
+    let sequences = sequence
+        .columns
+        .iter()
+        .filter_map(|c| c.processes.as_ref())
+        .chain(Some(&sequence.processes));
+    for processes in sequences {
+        for p in processes {
+            if let NameProcessor::AccentRestore(ref a) = p.processor {
+                for c in &a.input_corpus {
+                    if !columns.contains(c) {
+                        columns.push(c.clone());
+                    }
+                }
             }
-            RegexReplace(re) => rp::RegexReplace::new(&re.from, &re.to).map(WP::RegexReplace),
-            LogSuspicious(l) => rp::LogSuspicious::new(&l.regex).map(WP::LogSuspicious),
-            Decode(d) => Ok(WP::Decode(d)),
-            SnakeCase => Ok(WP::SnakeCase),
-            FirstLetterUppercase => Ok(WP::FirstLetterUppercase),
+        }
+    }
+    Ok(columns)
+}
+
+/// read the specific processing sequence of each column to be processed
+/// (None if the column uses the default sequence).
+/// The columns are the ones given, or the ones listed in config if none is given,
+/// or DEFAULT_COLUMN if config doesn't list any either, a column given twice being processed once.
+pub fn read_columns_conf(
+    conf_file: &str,
+    columns: &[String],
+    caches: &SharedCaches,
+) -> Result<Vec<(String, Option<Vec<worker::Step>>)>> {
+    let mut sequence = read_sequence(conf_file)?;
+
+    let mut names: Vec<String> = if !columns.is_empty() {
+        columns.to_vec()
+    } else if !sequence.columns.is_empty() {
+        sequence.columns.iter().map(|c| c.name.clone()).collect()
+    } else {
+        vec![DEFAULT_COLUMN.to_string()]
+    };
+    let mut seen = HashSet::new();
+    names.retain(|name| {
+        let first = seen.insert(name.clone());
+        if !first {
+            warn!("Column {} is given twice, it is processed once", name);
+        }
+        first
+    });
+
+    names
+        .into_iter()
+        .map(|name| {
+            let processes = sequence
+                .columns
+                .iter_mut()
+                .find(|c| c.name == name)
+                .and_then(|c| c.processes.take());
+            let processors = match processes {
+                Some(p) => {
+                    let p = with_overrides(sequence.overrides.as_ref(), p, conf_file);
+                    Some(make_processors(p, conf_file, caches)
+                        .chain_err(|| format!("Could not read sequence of column {}", name))?)
+                }
+                None => None,
+            };
+            Ok((name, processors))
         })
         .collect()
 }
+
+impl NameProcessor {
+    fn kind(&self) -> &'static str {
+        use self::NameProcessor::*;
+        match *self {
+            Decode(_) => "Decode",
+            FirstLetterUppercase => "FirstLetterUppercase",
+            SnakeCase => "SnakeCase",
+            LowercaseWord(_) => "LowercaseWord",
+            UppercaseWord(_) => "UppercaseWord",
+            RegexReplace(_) => "RegexReplace",
+            IspellCheck(_) => "IspellCheck",
+            AccentRestore(_) => "AccentRestore",
+            LogSuspicious(_) => "LogSuspicious",
+            Overrides(_) => "Overrides",
+        }
+    }
+}
+
+fn make_processors(
+    processes: Vec<LabeledProcessor>,
+    conf_file: &str,
+    caches: &SharedCaches,
+) -> Result<Vec<worker::Step>> {
+    processes
+        .into_iter()
+        .map(|p| {
+            let kind = p.processor.kind();
+            let processor = make_processor(p.processor, conf_file, caches)?;
+            Ok(worker::Step {
+                kind,
+                label: p.label,
+                processor,
+            })
+        })
+        .collect()
+}
+
+fn make_processor(
+    processor: NameProcessor,
+    conf_file: &str,
+    caches: &SharedCaches,
+) -> Result<worker::Processor> {
+    use self::NameProcessor::*;
+    use worker::Processor as WP;
+
+    match processor {
+        LowercaseWord(lcw) => rp::FixedcaseProcessor::new(&lcw.words, rp::CaseSpecifier::Lower)
+            .chain_err(|| "Could not create LowercaseWord manager")
+            .map(WP::Fixedcase),
+        UppercaseWord(ucw) => rp::FixedcaseProcessor::new(&ucw.words, rp::CaseSpecifier::Upper)
+            .chain_err(|| "Could not create UppercaseWord manager")
+            .map(WP::Fixedcase),
+        IspellCheck(i) => {
+            // the conf_file is already valid, thus this can't fail
+            let conf_path = Path::new(conf_file).parent().unwrap();
+            let backend = spell_backend::new_backend(i.backend, &i.dictionnary, conf_path)?;
+            let mut ispell = ispell_wrapper::SpellCheck::new(backend);
+            if i.added_words.is_some() || i.cache_dir.is_some() {
+                let key = format!("{:?} {}", i.backend, i.dictionnary);
+                ispell.share_dictionary_hash(caches, &key)?;
+            }
+            let bano_files = corpus_files(&i.bano_files);
+            let candidates = i.candidates.as_ref().map(|f| conf_path.join(f));
+            if let Some(ref f) = i.added_words {
+                bano_reader::load_added_words(&mut ispell, &conf_path.join(f))?;
+            }
+            match i.cache_dir {
+                Some(ref d) => {
+                    let cache_dir = conf_path.join(d);
+                    bano_reader::populate_dict_with_cache(
+                        &bano_files,
+                        &mut ispell,
+                        conf_path,
+                        &i.thresholds,
+                        candidates.as_deref(),
+                        &cache_dir,
+                        &i.dictionnary,
+                        caches,
+                    )?;
+                    ispell.open_cache(&cache_dir, &i.dictionnary, caches)?;
+                }
+                None => {
+                    bano_reader::populate_dict_from_files(
+                        &bano_files,
+                        &mut ispell,
+                        conf_path,
+                        &i.thresholds,
+                        candidates.as_deref(),
+                    )?;
+                }
+            }
+            if let Some(ref f) = i.word_choices {
+                ispell.set_word_choices(ispell_wrapper::read_word_choices(&conf_path.join(f))?);
+            }
+            Ok(WP::Ispell(ispell))
+        }
+        AccentRestore(a) => {
+            // the conf_file is already valid, thus this can't fail
+            let conf_path = Path::new(conf_file).parent().unwrap();
+            let bano_files = corpus_files(&a.bano_files);
+            let mut restore = accent_restore::AccentRestore::new(
+                &a.dictionaries,
+                &bano_files,
+                conf_path,
+                a.thresholds,
+            ).chain_err(|| "Could not create AccentRestore manager")?;
+            restore.set_input_columns(a.input_corpus);
+            if let Some(ref f) = a.word_choices {
+                restore.set_word_choices(ispell_wrapper::read_word_choices(&conf_path.join(f))?);
+            }
+            Ok(WP::AccentRestore(restore))
+        }
+        RegexReplace(re) => rp::RegexReplace::new(&re.from, &re.to).map(WP::RegexReplace),
+        LogSuspicious(l) => rp::LogSuspicious::new(&l.regex, l.severity).map(WP::LogSuspicious),
+        Overrides(o) => {
+            // the conf_file is already valid, thus this can't fail
+            let conf_path = Path::new(conf_file).parent().unwrap();
+            overrides::Overrides::new(&conf_path.join(&o.file))
+                .chain_err(|| "Could not create Overrides manager")
+                .map(WP::Overrides)
+        }
+        Decode(d) => Ok(WP::Decode(d)),
+        SnakeCase => Ok(WP::SnakeCase),
+        FirstLetterUppercase => Ok(WP::FirstLetterUppercase),
+    }
+}
@@ -6,12 +6,18 @@ error_chain! {
         Csv(::csv::Error);
         Regex(::regex::Error);
         Fmt(::std::fmt::Error);
+        Zip(::zip::result::ZipError);
+        Json(::serde_json::Error);
     }
 
     errors {
-        ColumnNotFound(t: String) {
+        ColumnNotFound(t: String, available: Vec<String>) {
             description("column not found")
-            display("column {} not found", t)
+            display("column {} not found (available columns: {})", t, available.join(", "))
+        }
+        WarningsFound(nb: usize) {
+            description("warnings found")
+            display("{} warnings found", nb)
         }
     }
 }
@@ -0,0 +1,460 @@
+use bzip2::read::BzDecoder;
+use csv;
+use encoding::label::encoding_from_whatwg_label;
+use encoding::{CodecError, DecoderTrap, RawDecoder};
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression as GzCompression;
+use std::cmp;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::path::Path;
+use std::str;
+use xz2::read::XzDecoder;
+use errors::{Result, ResultExt};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Compression {
+    None,
+    Gzip,
+    Bzip2,
+    Xz,
+}
+impl Compression {
+    fn from_magic_bytes(bytes: &[u8]) -> Self {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Compression::Gzip
+        } else if bytes.starts_with(b"BZh") {
+            Compression::Bzip2
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Compression::Xz
+        } else {
+            Compression::None
+        }
+    }
+
+    fn from_extension(path: &Path) -> Self {
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("gz") => Compression::Gzip,
+            Some("bz2") => Compression::Bzip2,
+            Some("xz") => Compression::Xz,
+            _ => Compression::None,
+        }
+    }
+}
+
+/// true if the path is -, meaning stdin or stdout
+pub fn is_std(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// reader of the contents of a file (stdin if the path is -),
+/// decompressed if it is a gzip, bzip2 or xz file
+/// (according to its first bytes, or else to its extension)
+pub fn open(path: &Path) -> Result<Box<dyn Read>> {
+    let input: Box<dyn Read> = if is_std(path) {
+        Box::new(io::stdin())
+    } else {
+        Box::new(File::open(path).chain_err(|| format!("Could not open {}", path.display()))?)
+    };
+    let mut rdr = BufReader::new(input);
+    let compression = match Compression::from_magic_bytes(rdr.fill_buf()?) {
+        Compression::None => Compression::from_extension(path),
+        c => c,
+    };
+    Ok(match compression {
+        Compression::None => Box::new(rdr),
+        Compression::Gzip => Box::new(MultiGzDecoder::new(rdr)),
+        Compression::Bzip2 => Box::new(BzDecoder::new(rdr)),
+        Compression::Xz => Box::new(XzDecoder::new(rdr)),
+    })
+}
+
+/// output file (see create)
+pub enum Output {
+    Plain(Box<dyn Write>),
+    Gzip(GzEncoder<io::BufWriter<File>>),
+}
+impl Output {
+    /// flush the output, ending the compressed stream if any
+    /// (errors would be lost if it was ended when dropped)
+    pub fn finish(self) -> io::Result<()> {
+        match self {
+            Output::Plain(mut w) => w.flush(),
+            Output::Gzip(w) => w.finish()?.flush(),
+        }
+    }
+}
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            Output::Plain(ref mut w) => w.write(buf),
+            Output::Gzip(ref mut w) => w.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            Output::Plain(ref mut w) => w.flush(),
+            Output::Gzip(ref mut w) => w.flush(),
+        }
+    }
+}
+
+/// writer of a file (stdout if the path is -), compressed with gzip if its extension is .gz
+/// (the output must be finished once everything is written, see Output::finish)
+pub fn create(path: &Path) -> Result<Output> {
+    if is_std(path) {
+        return Ok(Output::Plain(Box::new(io::BufWriter::new(io::stdout()))));
+    }
+    let file = File::create(path).chain_err(|| format!("Could not create {}", path.display()))?;
+    let wtr = io::BufWriter::new(file);
+    Ok(match Compression::from_extension(path) {
+        Compression::Gzip => Output::Gzip(GzEncoder::new(wtr, GzCompression::default())),
+        _ => Output::Plain(Box::new(wtr)),
+    })
+}
+
+// bytes read to detect the encoding and the delimiter of a csv file
+const SNIFF_LEN: usize = 64 * 1024;
+// delimiters detected, the first one being the default
+const DELIMITERS: &[u8] = b",;\t|";
+
+/// encoding and delimiter of a csv file, None meaning detected from the file
+#[derive(Debug, Clone, PartialEq)]
+pub struct CsvFormat {
+    pub encoding: Option<String>,
+    pub delimiter: Option<u8>,
+}
+impl CsvFormat {
+    /// the encoding is a label like utf-8 or latin1, the delimiter a character or tab,
+    /// both being detected if auto
+    pub fn new(encoding: &str, delimiter: &str) -> Result<Self> {
+        let encoding = match encoding {
+            "auto" => None,
+            e => Some(encoding_name(e)?),
+        };
+        let delimiter = match delimiter {
+            "auto" => None,
+            "tab" | "\\t" => Some(b'\t'),
+            d if d.len() == 1 => Some(d.as_bytes()[0]),
+            d => bail!("Invalid delimiter {} (a single character, tab or auto)", d),
+        };
+        Ok(CsvFormat {
+            encoding,
+            delimiter,
+        })
+    }
+}
+
+fn encoding_name(label: &str) -> Result<String> {
+    encoding_from_whatwg_label(label)
+        .map(|e| e.whatwg_name().unwrap_or_else(|| e.name()).to_string())
+        .ok_or_else(|| format!("Could not find encoding from {}", label).into())
+}
+
+// the encoding given by the byte order mark, and the length of the mark
+fn detect_bom(bytes: &[u8]) -> Option<(&'static str, usize)> {
+    if bytes.starts_with(&[0xef, 0xbb, 0xbf]) {
+        Some(("utf-8", 3))
+    } else if bytes.starts_with(&[0xff, 0xfe]) {
+        Some(("utf-16le", 2))
+    } else if bytes.starts_with(&[0xfe, 0xff]) {
+        Some(("utf-16be", 2))
+    } else {
+        None
+    }
+}
+
+// utf-8 if the bytes are valid utf-8 (but for a truncated last character),
+// else windows-1252 (superset of latin1)
+fn detect_encoding(bytes: &[u8]) -> &'static str {
+    match str::from_utf8(bytes) {
+        Ok(_) => "utf-8",
+        Err(ref e) if e.error_len().is_none() => "utf-8",
+        Err(_) => "windows-1252",
+    }
+}
+
+// the delimiter appearing the most in the header line
+fn detect_delimiter(text: &str) -> u8 {
+    let header = text.lines().next().unwrap_or("");
+    let mut best = (DELIMITERS[0], 0);
+    for &d in DELIMITERS {
+        let nb = header.bytes().filter(|&b| b == d).count();
+        if nb > best.1 {
+            best = (d, nb);
+        }
+    }
+    best.0
+}
+
+// reader of a stream decoded to utf-8, failing at the first invalid sequence
+struct DecodingReader<R: BufRead> {
+    input: R,
+    decoder: Box<dyn RawDecoder>,
+    // for the error messages
+    path: String,
+    encoding: String,
+    // bytes of the input already decoded
+    pos: u64,
+    // decoded text, read up to start
+    text: String,
+    start: usize,
+    finished: bool,
+}
+impl<R: BufRead> DecodingReader<R> {
+    fn error(&self, pos: u64, e: &CodecError) -> io::Error {
+        let msg = format!(
+            "Could not decode {} as {} at byte {}: {} (see --input-encoding)",
+            self.path, self.encoding, pos, e.cause
+        );
+        io::Error::new(io::ErrorKind::InvalidData, msg)
+    }
+}
+impl<R: BufRead> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.start == self.text.len() && !self.finished {
+            self.text.clear();
+            self.start = 0;
+            let len = {
+                let input = self.input.fill_buf()?;
+                if input.is_empty() {
+                    self.finished = true;
+                    match self.decoder.raw_finish(&mut self.text) {
+                        Some(e) => return Err(self.error(self.pos, &e)),
+                        None => continue,
+                    }
+                }
+                let (processed, err) = self.decoder.raw_feed(input, &mut self.text);
+                if let Some(e) = err {
+                    return Err(self.error(self.pos + processed as u64, &e));
+                }
+                input.len()
+            };
+            self.input.consume(len);
+            self.pos += len as u64;
+        }
+        let len = cmp::min(buf.len(), self.text.len() - self.start);
+        buf[..len].copy_from_slice(&self.text.as_bytes()[self.start..self.start + len]);
+        self.start += len;
+        Ok(len)
+    }
+}
+
+/// csv reader of a file (decompressed, see open), decoded to utf-8
+/// without its byte order mark, and its format (detected if not given)
+pub fn open_csv(
+    path: &Path,
+    format: &CsvFormat,
+) -> Result<(csv::Reader<Box<dyn Read>>, CsvFormat)> {
+    let mut rdr = BufReader::with_capacity(SNIFF_LEN, open(path)?);
+    let (encoding, bom_len) = {
+        let head = rdr.fill_buf()?;
+        let bom = detect_bom(head);
+        let encoding = match (format.encoding.as_ref(), bom) {
+            (Some(e), _) => e.clone(),
+            (None, Some((e, _))) => e.to_string(),
+            (None, None) => detect_encoding(head).to_string(),
+        };
+        let bom_len = match bom {
+            Some((e, len)) if e == encoding => len,
+            _ => 0,
+        };
+        (encoding, bom_len)
+    };
+    rdr.consume(bom_len);
+
+    // the encoding is detected from the first bytes only, so even utf-8 is checked
+    // while reading, the error then telling about the encoding
+    let decoding = encoding_from_whatwg_label(&encoding)
+        .ok_or_else(|| format!("Could not find encoding from {}", encoding))?;
+    let delimiter = match format.delimiter {
+        Some(d) => d,
+        None => {
+            let head = decoding.decode(rdr.fill_buf()?, DecoderTrap::Replace);
+            detect_delimiter(&head.unwrap_or_default())
+        }
+    };
+    let input = DecodingReader {
+        input: rdr,
+        decoder: decoding.raw_decoder(),
+        path: path.display().to_string(),
+        encoding: encoding.clone(),
+        pos: bom_len as u64,
+        text: String::new(),
+        start: 0,
+        finished: false,
+    };
+    info!(
+        "Reading {} as {} with delimiter {:?}",
+        path.display(),
+        encoding,
+        delimiter as char
+    );
+
+    let rdr = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_reader(Box::new(input) as Box<dyn Read>);
+    let format = CsvFormat {
+        encoding: Some(encoding),
+        delimiter: Some(delimiter),
+    };
+    Ok((rdr, format))
+}
+
+#[cfg(test)]
+mod tests {
+    use encoding::label::encoding_from_whatwg_label;
+    use std::io::{BufReader, Read};
+    use std::path::PathBuf;
+    use std::{env, fs, process};
+    use super::{open_csv, CsvFormat, DecodingReader};
+
+    // reader decoding the bytes, read by chunks of capacity bytes
+    fn decoding<'a>(
+        bytes: &'a [u8],
+        encoding: &str,
+        capacity: usize,
+    ) -> DecodingReader<BufReader<&'a [u8]>> {
+        DecodingReader {
+            input: BufReader::with_capacity(capacity, bytes),
+            decoder: encoding_from_whatwg_label(encoding).unwrap().raw_decoder(),
+            path: "test.csv".to_string(),
+            encoding: encoding.to_string(),
+            pos: 0,
+            text: String::new(),
+            start: 0,
+            finished: false,
+        }
+    }
+
+    // the path of a temporary file with the given content
+    fn temp_file(name: &str, content: &[u8]) -> PathBuf {
+        let path = env::temp_dir().join(format!("ruspell-files-{}-{}", process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    // the format detected and the records read from the content
+    fn read_csv(name: &str, content: &[u8]) -> (CsvFormat, Vec<Vec<String>>) {
+        let path = temp_file(name, content);
+        let auto = CsvFormat::new("auto", "auto").unwrap();
+        let (mut rdr, format) = open_csv(&path, &auto).unwrap();
+        let mut records = vec![rdr.headers().unwrap().iter().map(String::from).collect()];
+        for rec in rdr.records() {
+            records.push(rec.unwrap().iter().map(String::from).collect());
+        }
+        fs::remove_file(&path).unwrap();
+        (format, records)
+    }
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        text.encode_utf16()
+            .flat_map(|c| vec![c as u8, (c >> 8) as u8])
+            .collect()
+    }
+
+    fn record(fields: &[&str]) -> Vec<String> {
+        fields.iter().map(|f| f.to_string()).collect()
+    }
+
+    #[test]
+    fn split_characters() {
+        let text = "id,name\n1,Crème brûlée €\n";
+        for capacity in 1..5 {
+            let mut decoded = String::new();
+            decoding(text.as_bytes(), "utf-8", capacity)
+                .read_to_string(&mut decoded)
+                .unwrap();
+            assert_eq!(decoded, text);
+        }
+        let mut decoded = String::new();
+        decoding(&utf16le(text), "utf-16le", 3)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, text);
+    }
+
+    #[test]
+    fn small_reads() {
+        let text = "Crème brûlée";
+        let mut rdr = decoding(text.as_bytes(), "utf-8", 2);
+        let mut decoded = vec![];
+        let mut buf = [0; 1];
+        while rdr.read(&mut buf).unwrap() == 1 {
+            decoded.push(buf[0]);
+        }
+        assert_eq!(decoded, text.as_bytes());
+    }
+
+    #[test]
+    fn latin1() {
+        let mut decoded = String::new();
+        decoding(b"caf\xe9", "windows-1252", 2)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "café");
+    }
+
+    #[test]
+    fn invalid_byte() {
+        let mut decoded = String::new();
+        let err = decoding(b"id,name\n1,caf\xe9\n", "utf-8", 4)
+            .read_to_string(&mut decoded)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("at byte 13"), "{}", err);
+        assert!(err.contains("--input-encoding"), "{}", err);
+    }
+
+    #[test]
+    fn invalid_byte_after_sniffing() {
+        let mut content = b"id,name\n".to_vec();
+        while content.len() < 70 * 1024 {
+            content.extend_from_slice(b"1,gare\n");
+        }
+        content.extend_from_slice(b"2,caf\xe9\n");
+        let path = temp_file("invalid_byte_after_sniffing", &content);
+        let auto = CsvFormat::new("auto", "auto").unwrap();
+        let (mut rdr, format) = open_csv(&path, &auto).unwrap();
+        assert_eq!(format.encoding, Some("utf-8".to_string()));
+        let err = rdr.records()
+            .find(|r| r.is_err())
+            .unwrap()
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("--input-encoding"), "{}", err);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn bom() {
+        let (format, records) = read_csv("bom_utf8", b"\xef\xbb\xbfid,name\n1,gare\n");
+        assert_eq!(format.encoding, Some("utf-8".to_string()));
+        assert_eq!(records, vec![record(&["id", "name"]), record(&["1", "gare"])]);
+
+        let mut utf16 = vec![0xff, 0xfe];
+        utf16.extend(utf16le("id,name\n1,café\n"));
+        let (format, records) = read_csv("bom_utf16", &utf16);
+        assert_eq!(format.encoding, Some("utf-16le".to_string()));
+        assert_eq!(records, vec![record(&["id", "name"]), record(&["1", "café"])]);
+    }
+
+    #[test]
+    fn delimiters() {
+        let (format, records) = read_csv("semicolon", b"id;name;desc\n1;gare, sud;\n");
+        assert_eq!(format.delimiter, Some(b';'));
+        assert_eq!(records[1], record(&["1", "gare, sud", ""]));
+
+        let (format, records) = read_csv("tab", b"id\tname\n1\tgare; sud\n");
+        assert_eq!(format.delimiter, Some(b'\t'));
+        assert_eq!(records[1], record(&["1", "gare; sud"]));
+
+        let (format, _) = read_csv("latin1", b"id;name\n1;caf\xe9\n");
+        assert_eq!(
+            (format.encoding, format.delimiter),
+            (Some("windows-1252".to_string()), Some(b';'))
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use csv;
+use conf;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use zip;
+use pipeline::{self, ColumnsPipeline, RecordWriter};
+use outputs::Outputs;
+use errors::{ErrorKind, Result, ResultExt};
+
+// (file, heading of its id, textual columns processed by default)
+const GTFS_FILES: &[(&str, &str, &[&str])] = &[
+    ("agency.txt", "agency_id", &["agency_name"]),
+    ("stops.txt", "stop_id", &["stop_name", "stop_desc"]),
+    ("routes.txt", "route_id", &["route_long_name", "route_desc"]),
+    ("trips.txt", "trip_id", &["trip_headsign"]),
+];
+
+enum FeedReader {
+    Dir(PathBuf),
+    Zip(zip::ZipArchive<File>),
+}
+impl FeedReader {
+    fn new(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            return Ok(FeedReader::Dir(path.to_path_buf()));
+        }
+        let file = File::open(path).chain_err(|| format!("Could not open {}", path.display()))?;
+        let archive = zip::ZipArchive::new(file)
+            .chain_err(|| format!("Could not read zip archive {}", path.display()))?;
+        Ok(FeedReader::Zip(archive))
+    }
+
+    fn file_names(&mut self) -> Result<Vec<String>> {
+        let mut names = vec![];
+        match *self {
+            FeedReader::Dir(ref dir) => for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_file() {
+                    names.push(entry.file_name().to_string_lossy().into_owned());
+                }
+            },
+            FeedReader::Zip(ref mut archive) => for i in 0..archive.len() {
+                let file = archive.by_index(i)?;
+                if !file.name().ends_with('/') {
+                    names.push(file.name().to_string());
+                }
+            },
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    fn open<'a>(&'a mut self, name: &str) -> Result<Box<dyn io::Read + 'a>> {
+        match *self {
+            FeedReader::Dir(ref dir) => Ok(Box::new(File::open(dir.join(name))?)),
+            FeedReader::Zip(ref mut archive) => Ok(Box::new(archive.by_name(name)?)),
+        }
+    }
+}
+
+enum FeedWriter {
+    Dir(PathBuf),
+    Zip(zip::ZipWriter<File>),
+}
+impl FeedWriter {
+    // the output is a zip archive if its extension is .zip, a directory otherwise
+    fn new(path: &Path) -> Result<Self> {
+        if path.extension().is_some_and(|e| e == "zip") {
+            let file =
+                File::create(path).chain_err(|| format!("Could not create {}", path.display()))?;
+            Ok(FeedWriter::Zip(zip::ZipWriter::new(file)))
+        } else {
+            fs::create_dir_all(path)
+                .chain_err(|| format!("Could not create directory {}", path.display()))?;
+            Ok(FeedWriter::Dir(path.to_path_buf()))
+        }
+    }
+
+    fn create<'a>(&'a mut self, name: &str) -> Result<Box<dyn io::Write + 'a>> {
+        match *self {
+            FeedWriter::Dir(ref dir) => Ok(Box::new(File::create(dir.join(name))?)),
+            FeedWriter::Zip(ref mut archive) => {
+                archive.start_file(name, zip::write::FileOptions::default())?;
+                Ok(Box::new(archive))
+            }
+        }
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if let FeedWriter::Zip(ref mut archive) = *self {
+            archive.finish()?;
+        }
+        Ok(())
+    }
+}
+
+/// process the textual columns of a GTFS feed (zip archive or directory),
+/// writing the processed feed (other files are copied verbatim)
+/// and the outcomes of all files (the ids being prefixed with the file name, see process_csv)
+pub fn process_feed(
+    input: &Path,
+    output: &Path,
+    conf_file: &str,
+    outputs: &mut Outputs,
+) -> Result<()> {
+    let listed_columns = conf::read_listed_columns(conf_file)?;
+    let columns: Vec<String> = if listed_columns.is_empty() {
+        GTFS_FILES
+            .iter()
+            .flat_map(|f| f.2.iter().map(|c| c.to_string()))
+            .collect()
+    } else {
+        listed_columns
+    };
+    let mut pipelines = ColumnsPipeline::from_conf(conf_file, &columns)
+        .chain_err(|| "Could not read config file")?;
+
+    let mut reader = FeedReader::new(input)?;
+
+    // names of the feed used as corpus by processors, read beforehand
+    let corpus_columns = conf::read_input_corpus_columns(conf_file)?;
+    if !corpus_columns.is_empty() {
+        let mut input_corpus = vec![];
+        // the columns are read in the files having them, each one must be in some file
+        let mut available: Vec<String> = vec![];
+        for name in reader.file_names()? {
+            if GTFS_FILES.iter().any(|f| f.0 == name) {
+                let mut rdr = csv::Reader::from_reader(reader.open(&name)?);
+                let headers = rdr.headers()
+                    .chain_err(|| format!("Can't find headers in {}", name))?
+                    .clone();
+                let columns: Vec<String> = corpus_columns
+                    .iter()
+                    .filter(|c| headers.iter().any(|h| h == c.as_str()))
+                    .cloned()
+                    .collect();
+                available.extend(headers.iter().map(String::from));
+                let names = pipeline::read_input_corpus(&mut rdr, &columns)
+                    .chain_err(|| format!("Could not read {}", name))?;
+                input_corpus.extend(names);
+            }
+        }
+        if let Some(c) = corpus_columns.iter().find(|c| !available.contains(c)) {
+            available.sort();
+            available.dedup();
+            bail!(ErrorKind::ColumnNotFound(c.clone(), available));
+        }
+        pipelines.add_input_corpus(&input_corpus);
+    }
+
+    let mut writer = FeedWriter::new(output)?;
+    for name in reader.file_names()? {
+        let gtfs_file = GTFS_FILES.iter().find(|f| f.0 == name);
+        let mut rdr = reader.open(&name)?;
+        let mut wtr = writer.create(&name)?;
+        match gtfs_file {
+            Some(&(_, heading_id, _)) => process_file(
+                rdr,
+                wtr,
+                outputs,
+                &name,
+                heading_id,
+                &mut pipelines,
+            ).chain_err(|| format!("Could not process {}", name))?,
+            None => {
+                io::copy(&mut rdr, &mut wtr).chain_err(|| format!("Could not copy {}", name))?;
+            }
+        }
+    }
+    writer.finish()
+}
+
+fn process_file<R: io::Read, W: io::Write>(
+    rdr: R,
+    wtr: W,
+    outputs: &mut Outputs,
+    name: &str,
+    heading_id: &str,
+    pipelines: &mut ColumnsPipeline,
+) -> Result<()> {
+    let mut rdr = csv::Reader::from_reader(rdr);
+    let mut wtr = RecordWriter::Csv(Box::new(csv::Writer::from_writer(wtr)));
+    let headers = rdr.headers()
+        .chain_err(|| "Can't find headers in input file")?
+        .clone();
+    // only configured columns present in this file are processed
+    let columns: Vec<String> = pipelines
+        .columns()
+        .into_iter()
+        .filter(|c| headers.iter().any(|h| h == c))
+        .collect();
+    // a file may not have its id (ex: agency_id is optional with a single agency),
+    // its records are then identified by their first processed column
+    let heading_id = if headers.iter().any(|h| h == heading_id) {
+        heading_id.to_string()
+    } else {
+        match columns.first() {
+            Some(c) => c.clone(),
+            None => headers.get(0).unwrap_or_default().to_string(),
+        }
+    };
+
+    pipeline::process_csv(
+        &mut rdr,
+        Some(&mut wtr),
+        outputs,
+        &heading_id,
+        &columns,
+        pipelines,
+        Some(name),
+    )
+}
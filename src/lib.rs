@@ -0,0 +1,35 @@
+extern crate bzip2;
+extern crate csv;
+extern crate encoding;
+#[macro_use]
+extern crate error_chain;
+extern crate flate2;
+extern crate ispell;
+#[macro_use]
+extern crate log;
+extern crate regex;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate serde_yaml;
+extern crate unicode_normalization;
+extern crate xz2;
+extern crate zip;
+
+pub mod utils;
+pub mod worker;
+pub mod records_reader;
+pub mod errors;
+pub mod files;
+pub mod conf;
+pub mod apply;
+pub mod gtfs;
+pub mod outputs;
+pub mod pipeline;
+pub mod pool;
+pub mod report;
+pub mod review;
+pub mod verbatim;
+
+pub use pipeline::{ColumnsPipeline, Modification, Outcome, Pipeline, RecordRule};
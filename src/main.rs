@@ -1,145 +1,634 @@
 extern crate csv;
-extern crate encoding;
 #[macro_use]
-extern crate error_chain;
-extern crate ispell;
-extern crate regex;
+extern crate log;
+extern crate ruspell;
 #[macro_use]
 extern crate serde_derive;
-extern crate serde_yaml;
+extern crate serde_json;
 #[macro_use]
 extern crate structopt;
-extern crate unicode_normalization;
-
-mod utils;
-mod worker;
-mod records_reader;
-mod errors;
-mod conf;
 
+use log::{Level, LevelFilter, Log, Metadata, Record};
 use structopt::StructOpt;
-use records_reader::Record;
 use std::io;
-use errors::{Result, ResultExt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::path::{Path, PathBuf};
+use ruspell::ColumnsPipeline;
+use ruspell::outputs::Outputs;
+use ruspell::pool::Pool;
+use ruspell::worker::{bano_reader, spell_backend};
+use ruspell::worker::bano_reader::{Column, CorpusFile, Thresholds};
+use ruspell::worker::ispell_wrapper::SpellCheck;
+use ruspell::worker::spell_backend::BackendKind;
+use ruspell::{apply, conf, files, gtfs, pipeline, review};
+use ruspell::files::CsvFormat;
+use ruspell::pipeline::RecordWriter;
+use ruspell::verbatim::VerbatimWriter;
+use ruspell::errors::{ErrorKind, Result, ResultExt};
+
+// Leveled logging on stderr, as text or as JSON lines.
+static LOGGER: StderrLogger = StderrLogger;
+static LOG_JSON: AtomicBool = AtomicBool::new(false);
+
+struct StderrLogger;
+
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    level: String,
+    target: &'a str,
+    message: String,
+}
+
+impl Log for StderrLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        use io::Write;
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        // a failing log is not worth failing the processing
+        let _ = if LOG_JSON.load(Ordering::Relaxed) {
+            let line = JsonLine {
+                level: record.level().to_string().to_lowercase(),
+                target: record.target(),
+                message: record.args().to_string(),
+            };
+            serde_json::to_writer(&mut stderr, &line)
+                .map_err(io::Error::from)
+                .and_then(|_| writeln!(stderr))
+        } else {
+            writeln!(stderr, "{}: {}", record.level(), record.args())
+        };
+    }
+
+    fn flush(&self) {}
+}
+
+/// log messages from Info, moved by the number of -v and -q given
+/// (saturating at Error and Debug), as JSON lines if json is true
+fn init_logger(verbose: u8, quiet: u8, json: bool) {
+    let level = match 3 + i16::from(verbose) - i16::from(quiet) {
+        i if i <= 1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        _ => Level::Debug,
+    };
+    LOG_JSON.store(json, Ordering::Relaxed);
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level.to_level_filter());
+    } else {
+        log::set_max_level(LevelFilter::Off);
+    }
+}
 
 #[derive(StructOpt)]
 struct Args {
     #[structopt(long = "input", short = "i",
                 help = "Path to input CSV file to be processed \
-                        (typically a GTFS stops.txt file).")]
-    input: String,
+                        (typically a GTFS stops.txt file), - for stdin.")]
+    input: Option<String>,
 
     #[structopt(long = "config", short = "c", help = "Path to configuration file to be read.")]
-    config: String,
+    config: Option<String>,
 
     #[structopt(long = "output", short = "o",
                 help = "Path to output CSV file after processing \
-                        (same as input, <name> column processed).")]
+                        (same as input, <name> column processed), - for stdout.")]
     output: Option<String>,
 
+    #[structopt(long = "preserve-format",
+                help = "Copy the records of the input verbatim to the output, \
+                        only the changed names being rewritten \
+                        (keeping quoting, line endings, byte order mark and encoding).")]
+    preserve_format: bool,
+
+    #[structopt(flatten)]
+    outputs: OutputArgs,
+
+    #[structopt(long = "id", short = "I", default_value = "stop_id",
+                help = "The heading name of the column that is the unique id of the record.")]
+    heading_id: String,
+
+    #[structopt(long = "name", short = "N",
+                help = "The heading name of a column that needs a spell_check \
+                        (can be repeated, default to the columns listed in config, \
+                        or stop_name).")]
+    heading_names: Vec<String>,
+
+    #[structopt(long = "input-encoding", default_value = "utf-8",
+                help = "Encoding of the input (ex: latin1), or auto to detect it \
+                        (byte order mark, else utf-8 if valid, else windows-1252). \
+                        The output is written in utf-8.")]
+    input_encoding: String,
+
+    #[structopt(long = "delimiter", default_value = ",",
+                help = "Delimiter of the input (a character or tab), or auto to detect it \
+                        from the header line (, ; tab or |). The output uses the same.")]
+    delimiter: String,
+
+    #[structopt(long = "jobs", short = "j", default_value = "1",
+                help = "Number of records processed in parallel \
+                        (each job has its own processors, including aspell).")]
+    jobs: usize,
+
+    #[structopt(short = "v", parse(from_occurrences),
+                help = "Log more details (can be repeated).")]
+    verbose: u8,
+
+    #[structopt(short = "q", parse(from_occurrences),
+                help = "Log only warnings, or only errors if repeated.")]
+    quiet: u8,
+
+    #[structopt(long = "log-json", help = "Log as JSON lines (on stderr).")]
+    log_json: bool,
+
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
+}
+
+#[derive(StructOpt)]
+struct OutputArgs {
     #[structopt(long = "rules", short = "r", default_value = "rules.csv",
                 help = "Path to output rules.csv file \
                         (modifications description).")]
     rules: String,
 
-    #[structopt(long = "id", short = "I", default_value = "stop_id",
-                help = "The heading name of the column that is the unique id of the record.")]
-    heading_id: String,
+    #[structopt(long = "ambiguities",
+                help = "Path to output CSV file of ambiguous aspell suggestions \
+                        (to be resolved by filling the choice column, \
+                        see word_choices in IspellCheck config).")]
+    ambiguities: Option<String>,
 
-    #[structopt(long = "name", short = "N", default_value = "stop_name",
-                help = "The heading name of the column that needs a spell_check.")]
-    heading_name: String,
-}
-
-/// management of all processing applied to names
-/// returns None if no change was applied,
-/// Some modified name otherwise
-fn process_record(
-    rec: &Record,
-    processors: &mut [worker::Processor],
-) -> Result<Option<RecordRule>> {
-    let mut new_name = rec.name.clone();
-    let mut modifications = vec![];
-    for (i, p) in processors.iter_mut().enumerate() {
-        let modified_name = p.apply(&new_name)?;
-        if modified_name != new_name {
-            modifications.push((i, modified_name.clone()));
-        }
-        new_name = modified_name;
+    #[structopt(long = "warnings",
+                help = "Path to output file of LogSuspicious matches \
+                        (JSON lines if ending with .json or .jsonl, CSV otherwise).")]
+    warnings: Option<String>,
+
+    #[structopt(long = "fail-on-warning",
+                help = "Exit with code 2 if a LogSuspicious match \
+                        has a severity of warning or error.")]
+    fail_on_warning: bool,
+
+    #[structopt(long = "report",
+                help = "Path to output HTML report of the modifications, for reviewing.")]
+    report: Option<String>,
+}
+impl OutputArgs {
+    fn outputs(&self) -> Result<Outputs> {
+        Outputs::new(
+            Path::new(&self.rules),
+            self.ambiguities.as_ref().map(Path::new),
+            self.warnings.as_ref().map(Path::new),
+            self.report.as_ref().map(Path::new),
+        )
     }
 
-    if rec.name == new_name && modifications.is_empty() {
-        Ok(None)
-    } else {
-        Ok(Some(RecordRule {
-            id: rec.id.clone(),
-            old_name: rec.name.clone(),
-            new_name,
-            debug: format!("{:?}", modifications),
-        }))
+    fn finish(&self, outputs: Outputs) -> Result<()> {
+        let nb_warnings = outputs.nb_warnings();
+        outputs.finish()?;
+        if self.fail_on_warning && nb_warnings > 0 {
+            return Err(ErrorKind::WarningsFound(nb_warnings).into());
+        }
+        Ok(())
     }
 }
 
-#[derive(Debug, Serialize)]
-struct RecordRule {
-    id: String,
-    old_name: String,
-    new_name: String,
-    debug: String,
+#[derive(StructOpt)]
+enum Command {
+    #[structopt(name = "gtfs",
+                about = "Process the textual columns of a whole GTFS feed \
+                         (agency, stops, routes and trips).")]
+    Gtfs {
+        #[structopt(long = "input", short = "i",
+                    help = "Path to input GTFS feed (zip archive or directory).")]
+        input: String,
+
+        #[structopt(long = "config", short = "c", help = "Path to configuration file to be read.")]
+        config: String,
+
+        #[structopt(long = "output", short = "o",
+                    help = "Path to output GTFS feed after processing \
+                            (zip archive if ending with .zip, directory otherwise).")]
+        output: String,
+
+        #[structopt(flatten)]
+        outputs: OutputArgs,
+    },
+
+    #[structopt(name = "apply",
+                about = "Apply a (possibly edited) rules file to the input CSV file, \
+                         without running the processing sequence.")]
+    Apply {
+        #[structopt(long = "input", short = "i", help = "Path to input CSV file to be rewritten.")]
+        input: String,
+
+        #[structopt(long = "rules", short = "r", default_value = "rules.csv",
+                    help = "Path to rules.csv file to be applied.")]
+        rules: String,
+
+        #[structopt(long = "output", short = "o",
+                    help = "Path to output CSV file after applying rules.")]
+        output: Option<String>,
+
+        #[structopt(long = "id", short = "I", default_value = "stop_id",
+                    help = "The heading name of the column that is the unique id of the record.")]
+        heading_id: String,
+
+        #[structopt(long = "name", short = "N", default_value = "stop_name",
+                    help = "The heading name of the column modified by rules without column.")]
+        heading_name: String,
+
+        #[structopt(long = "check-old-name",
+                    help = "Do not apply rules whose old_name does not match the current name.")]
+        check_old_name: bool,
+    },
+
+    #[structopt(name = "review",
+                about = "Review the rules (and ambiguities) one by one in the terminal, \
+                         saving the decisions to an overrides file.")]
+    Review {
+        #[structopt(long = "rules", short = "r", default_value = "rules.csv",
+                    help = "Path to rules.csv file to be reviewed.")]
+        rules: String,
+
+        #[structopt(long = "ambiguities", short = "a",
+                    help = "Path to ambiguities file to be reviewed.")]
+        ambiguities: Option<String>,
+
+        #[structopt(long = "overrides", short = "O",
+                    help = "Path to overrides file where decisions are saved \
+                            (records already in it are not reviewed again), \
+                            to be loaded by an Overrides processor \
+                            [default: overrides of config, or overrides.csv].")]
+        overrides: Option<String>,
+
+        #[structopt(long = "config", short = "c",
+                    help = "Path to configuration file giving the overrides file \
+                            (loaded first in every sequence by the next runs).")]
+        config: Option<String>,
+
+        #[structopt(long = "name", short = "N", default_value = "stop_name",
+                    help = "The heading name of the column modified by rules without column.")]
+        heading_name: String,
+    },
+
+    #[structopt(name = "build-dict",
+                about = "Compute the words added to the dictionary from BANO files, \
+                         to be loaded with added_words in IspellCheck config.")]
+    BuildDict {
+        #[structopt(long = "dictionnary", short = "d",
+                    help = "Dictionary of the spell-checker (aspell dictionary name, \
+                            or path of Hunspell files or word list).")]
+        dictionnary: String,
+
+        #[structopt(long = "backend", default_value = "aspell",
+                    help = "Spell-checker (aspell, hunspell or word_list).")]
+        backend: BackendKind,
+
+        #[structopt(long = "bano", short = "b", help = "Path to a BANO file (can be repeated).")]
+        bano_files: Vec<String>,
+
+        #[structopt(long = "delimiter", default_value = ",",
+                    help = "Delimiter of the BANO files.")]
+        delimiter: char,
+
+        #[structopt(long = "headers", help = "The BANO files have a header line.")]
+        has_headers: bool,
+
+        #[structopt(long = "column",
+                    help = "Index or header of a column containing words (can be repeated, \
+                            street and city of BANO by default).")]
+        columns: Vec<Column>,
+
+        #[structopt(long = "output", short = "o", help = "Path to output added words file.")]
+        output: String,
+
+        #[structopt(flatten)]
+        thresholds: ThresholdArgs,
+
+        #[structopt(long = "candidates",
+                    help = "Path to output csv file of the decisions about the words of the \
+                            BANO files.")]
+        candidates: Option<String>,
+    },
 }
 
-fn run() -> Result<()> {
-    let args = Args::from_args();
+#[derive(StructOpt, Debug)]
+struct ThresholdArgs {
+    #[structopt(long = "frequency-divisor", default_value = "100000",
+                help = "A dominant form is added if it appears at least \
+                        corpus size / frequency-divisor times.")]
+    frequency_divisor: u32,
 
-    let mut rdr_stops = csv::ReaderBuilder::new()
-        .from_path(&args.input)
-        .chain_err(|| "Could not open input file")?;
-    let (records, headers) =
-        records_reader::new_record_iter(&mut rdr_stops, &args.heading_id, &args.heading_name)?;
+    #[structopt(long = "dominance-ratio", default_value = "4",
+                help = "A form is dominant if it appears dominance-ratio times more \
+                        than any other one.")]
+    dominance_ratio: u32,
 
-    // producing rules to be applied to re-spell names
-    let mut wtr_rules =
-        csv::Writer::from_path(&args.rules).chain_err(|| "Could not open rules file")?;
-    wtr_rules
-        .serialize(&["id", "old_name", "new_name", "debug"])
-        .chain_err(|| "Could not write header of rules file")?;
+    #[structopt(long = "keep-uppercase", help = "Consider full-uppercase words.")]
+    keep_uppercase: bool,
+
+    #[structopt(long = "keep-digits", help = "Consider words containing a digit.")]
+    keep_digits: bool,
+}
+impl ThresholdArgs {
+    fn thresholds(&self) -> Thresholds {
+        Thresholds {
+            frequency_divisor: self.frequency_divisor,
+            dominance_ratio: self.dominance_ratio,
+            skip_uppercase: !self.keep_uppercase,
+            skip_digits: !self.keep_digits,
+        }
+    }
+}
+
+fn run_csv(args: &Args) -> Result<()> {
+    let input = args.input
+        .as_ref()
+        .ok_or("--input is required when no subcommand is given")?;
+    let config = args.config
+        .as_ref()
+        .ok_or("--config is required when no subcommand is given")?;
+
+    let format = CsvFormat::new(&args.input_encoding, &args.delimiter)?;
+    let (mut rdr_stops, format) =
+        files::open_csv(Path::new(input), &format).chain_err(|| "Could not open input file")?;
 
     // producing output and replacing names only if requested (wtr_stops is an Option)
     let mut wtr_stops = match args.output {
-        Some(ref f) => Some(csv::Writer::from_path(f).chain_err(|| "Could not open output file")?),
+        Some(ref f) if args.preserve_format => {
+            if files::is_std(Path::new(input)) {
+                return Err("--preserve-format reads the input twice, it can't be stdin".into());
+            }
+            let raw_input = files::open(Path::new(input)).chain_err(|| "Could not open input file")?;
+            let wtr = files::create(Path::new(f)).chain_err(|| "Could not open output file")?;
+            let encoding = format.encoding.as_ref().map_or("utf-8", |e| e.as_str());
+            let delimiter = format.delimiter.unwrap_or(b',');
+            Some(RecordWriter::Verbatim(VerbatimWriter::new(raw_input, wtr, delimiter, encoding)?))
+        }
+        Some(ref f) => Some(RecordWriter::Csv(Box::new(create_output(f, &format)?))),
         None => None,
     };
-    wtr_stops
-        .as_mut()
-        .map_or(Ok(()), |w| w.write_record(&headers))
-        .chain_err(|| "Could not write header of output file")?;
-
-    //creating processor vector from config
-    let mut processors = conf::read_conf(&args.config).chain_err(|| "Could not read config file")?;
-
-    for res_rec in records {
-        let mut rec = res_rec.chain_err(|| format!("error at csv line decoding: {}", &args.input))?;
-        if let Some(rule) = process_record(&rec, &mut processors)? {
-            *rec.raw.get_mut(&args.heading_name).unwrap() = rule.new_name.clone();
-
-            wtr_rules
-                .serialize(&rule)
-                .chain_err(|| "Could not write into rules file")?;
-        }
 
-        let mut stop_record: Vec<&str> = Vec::with_capacity(headers.len());
-        for h in &headers {
-            stop_record.push(&rec.raw[h]);
+    // names of the input used as corpus by processors, read beforehand
+    let corpus_columns =
+        conf::read_input_corpus_columns(config).chain_err(|| "Could not read config file")?;
+    let input_corpus = if corpus_columns.is_empty() {
+        vec![]
+    } else {
+        if files::is_std(Path::new(input)) {
+            return Err("input_corpus reads the input twice, it can't be stdin".into());
         }
+        let (mut rdr, _) =
+            files::open_csv(Path::new(input), &format).chain_err(|| "Could not open input file")?;
+        pipeline::read_input_corpus(&mut rdr, &corpus_columns)
+            .chain_err(|| format!("Could not read {}", input))?
+    };
 
-        wtr_stops
-            .as_mut()
-            .map_or(Ok(()), |w| w.serialize(&stop_record))
+    if args.jobs > 1 {
+        // creating processing pipelines of every column from config, in every job
+        let mut pool = Pool::new(config, &args.heading_names, args.jobs, input_corpus)
+            .chain_err(|| "Could not read config file")?;
+
+        // producing rules to be applied to re-spell names
+        let mut outputs = args.outputs.outputs()?;
+        pipeline::process_csv_with_pool(
+            &mut rdr_stops,
+            wtr_stops.as_mut(),
+            &mut outputs,
+            &args.heading_id,
+            &mut pool,
+        ).chain_err(|| format!("Could not process {}", input))?;
+        finish_output(wtr_stops)?;
+        return args.outputs.finish(outputs);
+    }
+
+    //creating processing pipelines of every column from config
+    let mut pipelines = ColumnsPipeline::from_conf(config, &args.heading_names)
+        .chain_err(|| "Could not read config file")?;
+    pipelines.add_input_corpus(&input_corpus);
+
+    // producing rules to be applied to re-spell names
+    let mut outputs = args.outputs.outputs()?;
+
+    let columns = pipelines.columns();
+    pipeline::process_csv(
+        &mut rdr_stops,
+        wtr_stops.as_mut(),
+        &mut outputs,
+        &args.heading_id,
+        &columns,
+        &mut pipelines,
+        None,
+    ).chain_err(|| format!("Could not process {}", input))?;
+    finish_output(wtr_stops)?;
+    args.outputs.finish(outputs)
+}
+
+// ending the output once the records are written (see files::Output)
+fn finish_output(wtr: Option<RecordWriter<files::Output>>) -> Result<()> {
+    if let Some(w) = wtr {
+        w.into_inner()?
+            .finish()
             .chain_err(|| "Could not write into output file")?;
     }
     Ok(())
 }
 
+// the output csv, with the delimiter of the input
+fn create_output(path: &str, format: &CsvFormat) -> Result<csv::Writer<files::Output>> {
+    let wtr = files::create(Path::new(path)).chain_err(|| "Could not open output file")?;
+    Ok(csv::WriterBuilder::new()
+        .delimiter(format.delimiter.unwrap_or(b','))
+        .from_writer(wtr))
+}
+
+fn run_apply(
+    input: &str,
+    format: &CsvFormat,
+    rules: &str,
+    output: Option<&String>,
+    heading_id: &str,
+    heading_name: &str,
+    check_old_name: bool,
+) -> Result<()> {
+    let rules = apply::read_rules(Path::new(rules), heading_name)?;
+
+    let (mut rdr_stops, format) =
+        files::open_csv(Path::new(input), format).chain_err(|| "Could not open input file")?;
+    let mut wtr_stops = match output {
+        Some(f) => Some(create_output(f, &format)?),
+        None => None,
+    };
+
+    let report = apply::apply_rules(
+        &mut rdr_stops,
+        wtr_stops.as_mut(),
+        heading_id,
+        rules,
+        check_old_name,
+    ).chain_err(|| format!("Could not apply rules to {}", input))?;
+    finish_output(wtr_stops.map(|w| RecordWriter::Csv(Box::new(w))))?;
+
+    info!("Applied {} rules", report.nb_applied);
+    for (rule, name) in &report.drifted {
+        warn!(
+            "Drifted rule for {} ({}): expected {} but found {}",
+            rule.id, rule.column, rule.old_name, name
+        );
+    }
+    for rule in &report.stale {
+        warn!("Stale rule for {} ({}): record or column not found", rule.id, rule.column);
+    }
+    for rule in &report.conflicting {
+        warn!(
+            "Conflicting rule for {} ({}): {} -> {}",
+            rule.id, rule.column, rule.old_name, rule.new_name
+        );
+    }
+    Ok(())
+}
+
+fn run_review(
+    rules: &str,
+    ambiguities: Option<&String>,
+    overrides: &Path,
+    heading_name: &str,
+) -> Result<()> {
+    let rules = apply::read_rules(Path::new(rules), heading_name)?;
+    let ambiguities = match ambiguities {
+        Some(f) => review::read_ambiguities(Path::new(f))?,
+        None => vec![],
+    };
+
+    let stdin = io::stdin();
+    let report = review::review(
+        &rules,
+        &ambiguities,
+        overrides,
+        stdin.lock(),
+        io::stdout(),
+    ).chain_err(|| "Could not review rules")?;
+
+    info!(
+        "Accepted {}, rejected {}, edited {}, skipped {} ({} already decided)",
+        report.nb_accepted,
+        report.nb_rejected,
+        report.nb_edited,
+        report.nb_skipped,
+        report.nb_already_decided
+    );
+    Ok(())
+}
+
+fn run_build_dict(
+    dictionnary: &str,
+    backend: BackendKind,
+    bano_files: &[CorpusFile],
+    output: &str,
+    thresholds: &Thresholds,
+    candidates: Option<&String>,
+) -> Result<()> {
+    let backend = spell_backend::new_backend(backend, dictionnary, Path::new(""))?;
+    let mut ispell = SpellCheck::new(backend);
+    bano_reader::build_dict(
+        bano_files,
+        &mut ispell,
+        dictionnary,
+        Path::new(output),
+        thresholds,
+        candidates.map(Path::new),
+    )
+}
+
+fn run() -> Result<()> {
+    let args = Args::from_args();
+    init_logger(args.verbose, args.quiet, args.log_json);
+
+    match args.cmd {
+        Some(Command::Gtfs {
+            ref input,
+            ref config,
+            ref output,
+            outputs: ref output_args,
+        }) => {
+            let mut outputs = output_args.outputs()?;
+            gtfs::process_feed(Path::new(input), Path::new(output), config, &mut outputs)?;
+            output_args.finish(outputs)
+        }
+        Some(Command::Apply {
+            ref input,
+            ref rules,
+            ref output,
+            ref heading_id,
+            ref heading_name,
+            check_old_name,
+        }) => run_apply(
+            input,
+            &CsvFormat::new(&args.input_encoding, &args.delimiter)?,
+            rules,
+            output.as_ref(),
+            heading_id,
+            heading_name,
+            check_old_name,
+        ),
+        Some(Command::Review {
+            ref rules,
+            ref ambiguities,
+            ref overrides,
+            ref config,
+            ref heading_name,
+        }) => {
+            let overrides = match (overrides, config) {
+                (Some(f), _) => PathBuf::from(f),
+                (None, Some(c)) => conf::read_overrides_path(c)
+                    .chain_err(|| "Could not read config file")?
+                    .ok_or("No overrides file in config (see overrides)")?,
+                (None, None) => PathBuf::from("overrides.csv"),
+            };
+            run_review(rules, ambiguities.as_ref(), &overrides, heading_name)
+        }
+        Some(Command::BuildDict {
+            ref dictionnary,
+            backend,
+            ref bano_files,
+            delimiter,
+            has_headers,
+            ref columns,
+            ref output,
+            ref thresholds,
+            ref candidates,
+        }) => {
+            let bano_files: Vec<_> = bano_files
+                .iter()
+                .map(|f| {
+                    let mut corpus = CorpusFile::bano(f);
+                    corpus.delimiter = delimiter;
+                    corpus.has_headers = has_headers;
+                    if !columns.is_empty() {
+                        corpus.columns = columns.clone();
+                    }
+                    corpus
+                })
+                .collect();
+            run_build_dict(
+                dictionnary,
+                backend,
+                &bano_files,
+                output,
+                &thresholds.thresholds(),
+                candidates.as_ref(),
+            )
+        }
+        None => run_csv(&args),
+    }
+}
+
 fn main() {
     if let Err(ref e) = run() {
         use io::Write;
@@ -158,6 +647,9 @@ fn main() {
             writeln!(stderr, "backtrace: {:?}", backtrace).expect(errmsg);
         }
 
-        ::std::process::exit(1);
+        match *e.kind() {
+            ErrorKind::WarningsFound(_) => ::std::process::exit(2),
+            _ => ::std::process::exit(1),
+        }
     }
 }
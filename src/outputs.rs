@@ -0,0 +1,165 @@
+use csv;
+use serde_json;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use pipeline::{RecordOutcome, RecordWarning};
+use report::Report;
+use worker::regex_processor::Severity;
+use errors::{Result, ResultExt};
+
+/// an ambiguity to be resolved by filling its choice
+#[derive(Debug, Serialize)]
+struct AmbiguityRow<'a> {
+    id: &'a str,
+    column: &'a str,
+    name: &'a str,
+    misspelled: &'a str,
+    /// separated by |
+    suggestions: String,
+    choice: &'a str,
+}
+
+enum WarningsWriter {
+    Csv(Box<csv::Writer<File>>),
+    /// one json object per line
+    Json(File),
+}
+impl WarningsWriter {
+    // json lines if the extension is .json or .jsonl, csv otherwise
+    fn new(path: &Path) -> Result<Self> {
+        let is_json = path.extension()
+            .is_some_and(|e| e == "json" || e == "jsonl");
+        if is_json {
+            let file = File::create(path).chain_err(|| "Could not open warnings file")?;
+            Ok(WarningsWriter::Json(file))
+        } else {
+            let wtr = csv::Writer::from_path(path).chain_err(|| "Could not open warnings file")?;
+            Ok(WarningsWriter::Csv(Box::new(wtr)))
+        }
+    }
+
+    fn write(&mut self, warning: &RecordWarning) -> Result<()> {
+        match *self {
+            WarningsWriter::Csv(ref mut w) => w.serialize(warning)?,
+            WarningsWriter::Json(ref mut w) => {
+                serde_json::to_writer(&mut *w, warning)?;
+                writeln!(w)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match *self {
+            WarningsWriter::Csv(ref mut w) => w.flush()?,
+            WarningsWriter::Json(ref mut w) => w.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// Files describing the processing: rules, and optionally
+/// ambiguous spell-check suggestions, warnings and HTML report
+pub struct Outputs {
+    rules: csv::Writer<File>,
+    ambiguities: Option<csv::Writer<File>>,
+    warnings: Option<WarningsWriter>,
+    report: Option<(PathBuf, Report)>,
+    nb_warnings: usize,
+}
+impl Outputs {
+    pub fn new(
+        rules_path: &Path,
+        ambiguities_path: Option<&Path>,
+        warnings_path: Option<&Path>,
+        report_path: Option<&Path>,
+    ) -> Result<Self> {
+        let mut rules =
+            csv::Writer::from_path(rules_path).chain_err(|| "Could not open rules file")?;
+        rules
+            .serialize(["id", "column", "old_name", "new_name", "debug", "trace"])
+            .chain_err(|| "Could not write header of rules file")?;
+
+        let ambiguities = match ambiguities_path {
+            Some(p) => {
+                let mut w =
+                    csv::Writer::from_path(p).chain_err(|| "Could not open ambiguities file")?;
+                w.serialize(["id", "column", "name", "misspelled", "suggestions", "choice"])
+                    .chain_err(|| "Could not write header of ambiguities file")?;
+                Some(w)
+            }
+            None => None,
+        };
+
+        let warnings = match warnings_path {
+            Some(p) => Some(WarningsWriter::new(p)?),
+            None => None,
+        };
+
+        Ok(Outputs {
+            rules,
+            ambiguities,
+            warnings,
+            report: report_path.map(|p| (p.to_path_buf(), Report::new())),
+            nb_warnings: 0,
+        })
+    }
+
+    /// number of warnings met, with a severity of at least warning
+    pub fn nb_warnings(&self) -> usize {
+        self.nb_warnings
+    }
+
+    pub fn add(&mut self, outcome: RecordOutcome) -> Result<()> {
+        if let Some(ref rule) = outcome.rule {
+            self.rules
+                .serialize(rule)
+                .chain_err(|| "Could not write into rules file")?;
+        }
+        if let Some(ref mut w) = self.ambiguities {
+            for a in &outcome.ambiguities {
+                w.serialize(AmbiguityRow {
+                    id: &a.id,
+                    column: &a.column,
+                    name: &a.name,
+                    misspelled: &a.misspelled,
+                    suggestions: a.suggestions.join("|"),
+                    choice: "",
+                }).chain_err(|| "Could not write into ambiguities file")?;
+            }
+        }
+        self.nb_warnings += outcome
+            .warnings
+            .iter()
+            .filter(|w| w.severity >= Severity::Warning)
+            .count();
+        if let Some(ref mut w) = self.warnings {
+            for warning in &outcome.warnings {
+                w.write(warning)
+                    .chain_err(|| "Could not write into warnings file")?;
+            }
+        }
+        if let Some((_, ref mut report)) = self.report {
+            report.add(outcome)?;
+        }
+        Ok(())
+    }
+
+    /// flush the files and write the report
+    pub fn finish(self) -> Result<()> {
+        let mut rules = self.rules;
+        rules.flush()?;
+        if let Some(mut w) = self.ambiguities {
+            w.flush()?;
+        }
+        if let Some(mut w) = self.warnings {
+            w.flush()?;
+        }
+        if let Some((path, report)) = self.report {
+            let mut wtr_report = File::create(path).chain_err(|| "Could not open report file")?;
+            report.write_html(&mut wtr_report)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,476 @@
+use conf;
+use csv;
+use serde_json;
+use std::io;
+use worker;
+use worker::ispell_wrapper::Ambiguity;
+use worker::regex_processor::{Severity, Suspicion};
+use worker::spell_cache::SharedCaches;
+use records_reader::{self, Record};
+use outputs::Outputs;
+use pool::Pool;
+use verbatim::VerbatimWriter;
+use errors::{Result, ResultExt};
+
+/// Change of the name made by one processor of the sequence
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Modification {
+    /// index of the processor in the sequence
+    pub index: usize,
+    /// type of the processor (ex: RegexReplace)
+    pub processor: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub label: Option<String>,
+    pub before: String,
+    pub after: String,
+}
+
+/// Result of a name going through the whole processing sequence
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub new_name: String,
+    /// every processor that changed the name
+    pub modifications: Vec<Modification>,
+    /// true if the name was forced by an Overrides processor
+    /// (the following processors are then skipped)
+    pub overridden: bool,
+    /// ambiguous spell-check suggestions met while processing
+    pub ambiguities: Vec<Ambiguity>,
+    /// (index of the processor in the sequence, its label, suspicious match)
+    pub suspicions: Vec<(usize, Option<String>, Suspicion)>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RecordRule {
+    pub id: String,
+    #[serde(default)]
+    pub column: String,
+    pub old_name: String,
+    pub new_name: String,
+    /// (index of the processor, name produced) of the modifications,
+    /// starting with override if the name was forced by an Overrides processor
+    #[serde(default)]
+    pub debug: String,
+    /// json array of the modifications
+    #[serde(default)]
+    pub trace: String,
+}
+impl RecordRule {
+    pub fn modifications(&self) -> Result<Vec<Modification>> {
+        serde_json::from_str(&self.trace).chain_err(|| format!("Invalid trace {}", self.trace))
+    }
+}
+
+/// Ambiguous spell-check suggestions met while processing the name of a record
+#[derive(Debug, Clone)]
+pub struct RecordAmbiguity {
+    pub id: String,
+    pub column: String,
+    pub name: String,
+    pub misspelled: String,
+    pub suggestions: Vec<String>,
+}
+
+/// Suspicious match (LogSuspicious) in the name of a record
+#[derive(Debug, Clone, Serialize)]
+pub struct RecordWarning {
+    pub id: String,
+    pub column: String,
+    /// index of the processor in the sequence
+    pub index: usize,
+    pub label: Option<String>,
+    pub severity: Severity,
+    pub matched: String,
+    /// byte span of the match in the name
+    pub start: usize,
+    pub end: usize,
+    /// the name when the match occurred
+    pub name: String,
+}
+
+/// What the processing of the name of a record produced
+#[derive(Debug)]
+pub struct RecordOutcome {
+    /// None if no change was applied to the name
+    pub rule: Option<RecordRule>,
+    pub ambiguities: Vec<RecordAmbiguity>,
+    pub warnings: Vec<RecordWarning>,
+}
+
+/// Sequence of processors applied to names, built from the config file
+pub struct Pipeline {
+    steps: Vec<worker::Step>,
+}
+impl Pipeline {
+    pub fn new(steps: Vec<worker::Step>) -> Self {
+        Pipeline { steps }
+    }
+
+    pub fn from_conf(conf_file: &str) -> Result<Self> {
+        Pipeline::from_conf_with_caches(conf_file, &SharedCaches::default())
+    }
+
+    /// pipeline whose processors share their caches (see cache_dir of IspellCheck)
+    pub fn from_conf_with_caches(conf_file: &str, caches: &SharedCaches) -> Result<Self> {
+        conf::read_conf(conf_file, caches).map(Pipeline::new)
+    }
+
+    /// management of all processing applied to a name
+    pub fn process(&mut self, name: &str) -> Result<Outcome> {
+        self.process_named(None, None, name)
+    }
+
+    /// processing of the name of a record, Overrides processors
+    /// can then also match the record id (and column)
+    pub fn process_named(
+        &mut self,
+        id: Option<&str>,
+        column: Option<&str>,
+        name: &str,
+    ) -> Result<Outcome> {
+        let mut new_name = name.to_string();
+        let mut modifications = vec![];
+        let mut overridden = false;
+        let mut ambiguities = vec![];
+        let mut suspicions = vec![];
+        for (i, s) in self.steps.iter_mut().enumerate() {
+            let forced_name = s.processor.forced_name(id, column, name);
+            overridden = forced_name.is_some();
+            let modified_name = match forced_name {
+                Some(n) => n,
+                None => s.processor.apply(&new_name)?,
+            };
+            ambiguities.extend(s.processor.take_ambiguities());
+            for suspicion in s.processor.take_suspicions() {
+                suspicions.push((i, s.label.clone(), suspicion));
+            }
+            if modified_name != new_name {
+                modifications.push(Modification {
+                    index: i,
+                    processor: s.kind.to_string(),
+                    label: s.label.clone(),
+                    before: new_name,
+                    after: modified_name.clone(),
+                });
+            }
+            new_name = modified_name;
+            if overridden {
+                break;
+            }
+        }
+        Ok(Outcome {
+            new_name,
+            modifications,
+            overridden,
+            ambiguities,
+            suspicions,
+        })
+    }
+
+    /// names of the input (column, name) to be used as corpus, see read_input_corpus
+    pub fn add_input_corpus(&mut self, names: &[(String, String)]) {
+        for s in &mut self.steps {
+            s.processor.add_input_corpus(names);
+        }
+    }
+
+    /// processing of the name of a record, with the rule describing the modification
+    /// if a change was applied
+    pub fn process_record(&mut self, id: &str, column: &str, name: &str) -> Result<RecordOutcome> {
+        let outcome = self.process_named(Some(id), Some(column), name)?;
+
+        let ambiguities = outcome
+            .ambiguities
+            .into_iter()
+            .map(|a| RecordAmbiguity {
+                id: id.to_string(),
+                column: column.to_string(),
+                name: name.to_string(),
+                misspelled: a.misspelled,
+                suggestions: a.suggestions,
+            })
+            .collect();
+        let warnings = outcome
+            .suspicions
+            .into_iter()
+            .map(|(index, label, s)| RecordWarning {
+                id: id.to_string(),
+                column: column.to_string(),
+                index,
+                label,
+                severity: s.severity,
+                matched: s.matched,
+                start: s.start,
+                end: s.end,
+                name: s.name,
+            })
+            .collect();
+        let rule = if name == outcome.new_name && outcome.modifications.is_empty() {
+            None
+        } else {
+            let changes: Vec<_> = outcome
+                .modifications
+                .iter()
+                .map(|m| (m.index, &m.after))
+                .collect();
+            let debug = if outcome.overridden {
+                format!("override {:?}", changes)
+            } else {
+                format!("{:?}", changes)
+            };
+            Some(RecordRule {
+                id: id.to_string(),
+                column: column.to_string(),
+                old_name: name.to_string(),
+                new_name: outcome.new_name,
+                debug,
+                trace: serde_json::to_string(&outcome.modifications)?,
+            })
+        };
+        Ok(RecordOutcome {
+            rule,
+            ambiguities,
+            warnings,
+        })
+    }
+}
+
+/// Pipelines applied to each processed column of a record
+/// (columns without a specific sequence in config share the default pipeline)
+pub struct ColumnsPipeline {
+    /// (column, index of its pipeline)
+    columns: Vec<(String, usize)>,
+    pipelines: Vec<Pipeline>,
+}
+impl ColumnsPipeline {
+    pub fn from_conf(conf_file: &str, columns: &[String]) -> Result<Self> {
+        ColumnsPipeline::from_conf_with_caches(conf_file, columns, &SharedCaches::default())
+    }
+
+    /// pipelines whose processors share their caches (see cache_dir of IspellCheck)
+    pub fn from_conf_with_caches(
+        conf_file: &str,
+        columns: &[String],
+        caches: &SharedCaches,
+    ) -> Result<Self> {
+        let mut pipelines = vec![];
+        let mut default_index = None;
+        let mut indexed_columns = vec![];
+        for (column, processors) in conf::read_columns_conf(conf_file, columns, caches)? {
+            let index = match processors {
+                Some(p) => {
+                    pipelines.push(Pipeline::new(p));
+                    pipelines.len() - 1
+                }
+                None => match default_index {
+                    Some(i) => i,
+                    None => {
+                        pipelines.push(Pipeline::from_conf_with_caches(conf_file, caches)?);
+                        default_index = Some(pipelines.len() - 1);
+                        pipelines.len() - 1
+                    }
+                },
+            };
+            indexed_columns.push((column, index));
+        }
+        Ok(ColumnsPipeline {
+            columns: indexed_columns,
+            pipelines,
+        })
+    }
+
+    pub fn columns(&self) -> Vec<String> {
+        self.columns.iter().map(|c| c.0.clone()).collect()
+    }
+
+    pub fn pipeline_mut(&mut self, column: &str) -> Option<&mut Pipeline> {
+        let index = self.columns.iter().find(|c| c.0 == column).map(|c| c.1);
+        match index {
+            Some(i) => Some(&mut self.pipelines[i]),
+            None => None,
+        }
+    }
+
+    pub fn add_input_corpus(&mut self, names: &[(String, String)]) {
+        for p in &mut self.pipelines {
+            p.add_input_corpus(names);
+        }
+    }
+
+    /// outcomes of every processed column of the record, in the order of the columns
+    pub fn process_record(&mut self, rec: &Record) -> Result<Vec<RecordOutcome>> {
+        let mut outcomes = vec![];
+        for (column, name) in &rec.names {
+            if let Some(pipeline) = self.pipeline_mut(column) {
+                outcomes.push(pipeline.process_record(&rec.id, column, name)?);
+            }
+        }
+        Ok(outcomes)
+    }
+}
+
+/// names (column, name) of the given columns of every record of the csv
+/// (a column missing from the csv is an error)
+pub fn read_input_corpus<R: io::Read>(
+    rdr: &mut csv::Reader<R>,
+    columns: &[String],
+) -> Result<Vec<(String, String)>> {
+    let headers = rdr.headers()
+        .chain_err(|| "Can't find headers in input file")?
+        .clone();
+    let positions = columns
+        .iter()
+        .map(|c| records_reader::column_position(&headers, c).map(|pos| (c, pos)))
+        .collect::<Result<Vec<_>>>()?;
+    let mut names = vec![];
+    for res_rec in rdr.records() {
+        let rec = res_rec.chain_err(|| "error at csv line decoding")?;
+        for &(column, pos) in &positions {
+            if let Some(name) = rec.get(pos) {
+                names.push((column.clone(), name.to_string()));
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// writer of the processed records
+pub enum RecordWriter<W: io::Write> {
+    /// the records are written from their values
+    Csv(Box<csv::Writer<W>>),
+    /// the records are copied from the input, but for the changed names
+    Verbatim(VerbatimWriter<W>),
+}
+impl<W: io::Write> RecordWriter<W> {
+    fn write_headers(&mut self, headers: &csv::StringRecord) -> Result<()> {
+        match *self {
+            RecordWriter::Csv(ref mut w) => w.write_record(headers)?,
+            RecordWriter::Verbatim(ref mut w) => w.write_headers()?,
+        }
+        Ok(())
+    }
+
+    // flushing the output at the end (copying the end of the input if verbatim)
+    fn finish(&mut self) -> Result<()> {
+        match *self {
+            RecordWriter::Csv(ref mut w) => w.flush()?,
+            RecordWriter::Verbatim(ref mut w) => w.finish()?,
+        }
+        Ok(())
+    }
+
+    /// the output, once the records are written
+    pub fn into_inner(self) -> Result<W> {
+        match self {
+            RecordWriter::Csv(w) => (*w).into_inner().map_err(|e| e.to_string().into()),
+            RecordWriter::Verbatim(w) => Ok(w.into_inner()),
+        }
+    }
+}
+
+// records processed by a worker at once
+const BATCH_SIZE: usize = 100;
+
+/// process the given columns of every record of the csv,
+/// writing the outcomes (rules describing modifications...)
+/// and the processed records if an output is given,
+/// the ids of the records being prefixed with the name of the file if given (ex: stops.txt:42)
+pub fn process_csv<R: io::Read, W: io::Write>(
+    rdr: &mut csv::Reader<R>,
+    mut wtr: Option<&mut RecordWriter<W>>,
+    outputs: &mut Outputs,
+    heading_id: &str,
+    columns: &[String],
+    pipelines: &mut ColumnsPipeline,
+    file: Option<&str>,
+) -> Result<()> {
+    let (records, headers) = records_reader::new_record_iter(rdr, heading_id, columns)?;
+
+    if let Some(ref mut w) = wtr {
+        w.write_headers(&headers)
+            .chain_err(|| "Could not write header of output file")?;
+    }
+
+    for res_rec in records {
+        let mut rec = res_rec.chain_err(|| "error at csv line decoding")?;
+        if let Some(f) = file {
+            rec.id = format!("{}:{}", f, rec.id);
+        }
+        let outcomes = pipelines.process_record(&rec)?;
+        write_record(rec, outcomes, &headers, wtr.as_deref_mut(), outputs)?;
+    }
+    if let Some(w) = wtr {
+        w.finish().chain_err(|| "Could not write into output file")?;
+    }
+    Ok(())
+}
+
+/// same as process_csv, the records being processed by the workers of the pool
+/// (the outcomes and records are written in the order of the input)
+pub fn process_csv_with_pool<R: io::Read, W: io::Write>(
+    rdr: &mut csv::Reader<R>,
+    mut wtr: Option<&mut RecordWriter<W>>,
+    outputs: &mut Outputs,
+    heading_id: &str,
+    pool: &mut Pool,
+) -> Result<()> {
+    let columns = pool.columns();
+    let (records, headers) = records_reader::new_record_iter(rdr, heading_id, &columns)?;
+
+    if let Some(ref mut w) = wtr {
+        w.write_headers(&headers)
+            .chain_err(|| "Could not write header of output file")?;
+    }
+
+    let mut records = records.peekable();
+    let batches = ::std::iter::from_fn(move || {
+        records.peek()?;
+        let batch: Result<Vec<Record>> = records
+            .by_ref()
+            .take(BATCH_SIZE)
+            .map(|r| r.chain_err(|| "error at csv line decoding"))
+            .collect();
+        Some(batch)
+    });
+    pool.process(batches, |rec, outcomes| {
+        write_record(rec, outcomes, &headers, wtr.as_deref_mut(), outputs)
+    })?;
+    if let Some(w) = wtr {
+        w.finish().chain_err(|| "Could not write into output file")?;
+    }
+    Ok(())
+}
+
+fn write_record<W: io::Write>(
+    mut rec: Record,
+    outcomes: Vec<RecordOutcome>,
+    headers: &csv::StringRecord,
+    wtr: Option<&mut RecordWriter<W>>,
+    outputs: &mut Outputs,
+) -> Result<()> {
+    // (index of the column, new name) of the changed names
+    let mut changes = vec![];
+    for outcome in outcomes {
+        if let Some(ref rule) = outcome.rule {
+            let pos = records_reader::column_position(headers, &rule.column)?;
+            if rule.new_name != rule.old_name {
+                changes.push((pos, rule.new_name.clone()));
+            }
+            rec.raw[pos] = rule.new_name.clone();
+        }
+        outputs.add(outcome)?;
+    }
+
+    match wtr {
+        Some(&mut RecordWriter::Csv(ref mut w)) => {
+            w.write_record(&rec.raw)
+                .chain_err(|| "Could not write into output file")?;
+        }
+        Some(&mut RecordWriter::Verbatim(ref mut w)) => {
+            w.write_record(rec.raw.len(), &changes)
+                .chain_err(|| "Could not write into output file")?;
+        }
+        None => {}
+    }
+    Ok(())
+}
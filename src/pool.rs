@@ -0,0 +1,264 @@
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use pipeline::{ColumnsPipeline, RecordOutcome};
+use records_reader::Record;
+use worker::spell_cache::SharedCaches;
+use errors::{Result, ResultExt};
+
+type BatchResult = Result<Vec<(Record, Vec<RecordOutcome>)>>;
+
+struct Worker {
+    batches: mpsc::Sender<Vec<Record>>,
+    results: mpsc::Receiver<BatchResult>,
+    handle: thread::JoinHandle<()>,
+}
+impl Worker {
+    // the pipelines are built by the thread (processors can't be shared, but their caches are)
+    fn spawn(
+        conf_file: &str,
+        columns: &[String],
+        input_corpus: &Arc<Vec<(String, String)>>,
+        caches: &SharedCaches,
+    ) -> Result<(Self, Vec<String>)> {
+        let (batches, rx_batches) = mpsc::channel::<Vec<Record>>();
+        let (tx_results, results) = mpsc::channel();
+        let (tx_init, rx_init) = mpsc::channel();
+        let conf_file = conf_file.to_string();
+        let columns = columns.to_vec();
+        let input_corpus = input_corpus.clone();
+        let caches = caches.clone();
+        let handle = thread::spawn(move || {
+            let pipelines = ColumnsPipeline::from_conf_with_caches(&conf_file, &columns, &caches);
+            let mut pipelines = match pipelines {
+                Ok(mut p) => {
+                    p.add_input_corpus(&input_corpus);
+                    let _ = tx_init.send(Ok(p.columns()));
+                    p
+                }
+                Err(e) => {
+                    let _ = tx_init.send(Err(e));
+                    return;
+                }
+            };
+            for batch in rx_batches {
+                let res: BatchResult = batch
+                    .into_iter()
+                    .map(|rec| {
+                        let outcomes = pipelines.process_record(&rec)?;
+                        Ok((rec, outcomes))
+                    })
+                    .collect();
+                if tx_results.send(res).is_err() {
+                    return;
+                }
+            }
+        });
+        let columns = rx_init
+            .recv()
+            .chain_err(|| "Worker stopped unexpectedly")??;
+        let worker = Worker {
+            batches,
+            results,
+            handle,
+        };
+        Ok((worker, columns))
+    }
+}
+
+/// threads processing batches of records, each with its own pipelines built from config
+pub struct Pool {
+    workers: Vec<Worker>,
+    columns: Vec<String>,
+}
+impl Pool {
+    /// input_corpus: names of the input used as corpus (see pipeline::read_input_corpus)
+    pub fn new(
+        conf_file: &str,
+        columns: &[String],
+        jobs: usize,
+        input_corpus: Vec<(String, String)>,
+    ) -> Result<Self> {
+        let input_corpus = Arc::new(input_corpus);
+        // the caches (see cache_dir of IspellCheck) are opened by the first worker,
+        // built alone, the others sharing them: each cache file has a single writer
+        let caches = SharedCaches::default();
+        let (first, columns_conf) = Worker::spawn(conf_file, columns, &input_corpus, &caches)?;
+        let mut workers = vec![first];
+        let others: Vec<_> = (1..jobs)
+            .map(|_| {
+                let conf_file = conf_file.to_string();
+                let columns = columns.to_vec();
+                let input_corpus = input_corpus.clone();
+                let caches = caches.clone();
+                thread::spawn(move || {
+                    Worker::spawn(&conf_file, &columns, &input_corpus, &caches).map(|w| w.0)
+                })
+            })
+            .collect();
+        for handle in others {
+            let worker = handle
+                .join()
+                .map_err(|_| "Worker stopped unexpectedly")?
+                .chain_err(|| "Could not create worker")?;
+            workers.push(worker);
+        }
+        Ok(Pool {
+            workers,
+            columns: columns_conf,
+        })
+    }
+
+    pub fn columns(&self) -> Vec<String> {
+        self.columns.clone()
+    }
+
+    /// process the batches with the workers in turn, the records and their outcomes
+    /// being given to f in the order of the batches
+    pub fn process<I, F>(&mut self, batches: I, mut f: F) -> Result<()>
+    where
+        I: Iterator<Item = Result<Vec<Record>>>,
+        F: FnMut(Record, Vec<RecordOutcome>) -> Result<()>,
+    {
+        // index of the worker of each batch being processed
+        let mut pending = VecDeque::new();
+        let mut next_worker = 0;
+        for batch in batches {
+            // at most 2 batches per worker are pending
+            if pending.len() == 2 * self.workers.len() {
+                self.receive(&mut pending, &mut f)?;
+            }
+            self.workers[next_worker]
+                .batches
+                .send(batch?)
+                .map_err(|_| "Worker stopped unexpectedly")?;
+            pending.push_back(next_worker);
+            next_worker = (next_worker + 1) % self.workers.len();
+        }
+        while !pending.is_empty() {
+            self.receive(&mut pending, &mut f)?;
+        }
+        Ok(())
+    }
+
+    fn receive<F>(&self, pending: &mut VecDeque<usize>, f: &mut F) -> Result<()>
+    where
+        F: FnMut(Record, Vec<RecordOutcome>) -> Result<()>,
+    {
+        if let Some(w) = pending.pop_front() {
+            let results = self.workers[w]
+                .results
+                .recv()
+                .map_err(|_| "Worker stopped unexpectedly")??;
+            for (rec, outcomes) in results {
+                f(rec, outcomes)?;
+            }
+        }
+        Ok(())
+    }
+}
+impl Drop for Pool {
+    fn drop(&mut self) {
+        for w in self.workers.drain(..) {
+            // closing the channel of batches stops the thread
+            drop(w.batches);
+            let _ = w.handle.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use std::{env, fs, process};
+    use pipeline::{ColumnsPipeline, RecordOutcome};
+    use records_reader::Record;
+    use super::Pool;
+
+    const COLUMNS: &[&str] = &["stop_name"];
+
+    // the path of a temporary config file with the given content
+    fn temp_conf(name: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("ruspell-pool-{}-{}.yml", process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn records(nb: usize) -> Vec<Record> {
+        (0..nb)
+            .map(|i| {
+                // every third name is unchanged
+                let name = if i % 3 == 0 {
+                    format!("Rue {}", i)
+                } else {
+                    format!("rue {}", i)
+                };
+                Record {
+                    id: i.to_string(),
+                    names: vec![(COLUMNS[0].to_string(), name.clone())],
+                    raw: vec![i.to_string(), name],
+                }
+            })
+            .collect()
+    }
+
+    // (id, new name if changed) of the outcomes
+    fn summary(rec: &Record, outcomes: &[RecordOutcome]) -> Vec<(String, Option<String>)> {
+        outcomes
+            .iter()
+            .map(|o| (rec.id.clone(), o.rule.as_ref().map(|r| r.new_name.clone())))
+            .collect()
+    }
+
+    fn process_with_pool(
+        conf: &str,
+        jobs: usize,
+        nb: usize,
+    ) -> super::Result<Vec<(String, Option<String>)>> {
+        let columns: Vec<String> = COLUMNS.iter().map(|c| c.to_string()).collect();
+        let mut pool = Pool::new(conf, &columns, jobs, vec![])?;
+        let mut batches = vec![];
+        for (i, rec) in records(nb).into_iter().enumerate() {
+            if i % 100 == 0 {
+                batches.push(vec![]);
+            }
+            batches.last_mut().unwrap().push(rec);
+        }
+        let batches = batches.into_iter().map(Ok::<Vec<Record>, _>);
+        let mut processed = vec![];
+        pool.process(batches, |rec, outcomes| {
+            processed.extend(summary(&rec, &outcomes));
+            Ok(())
+        })?;
+        Ok(processed)
+    }
+
+    #[test]
+    fn order_of_the_input() {
+        let conf = temp_conf("order", "processes:\n  - SnakeCase\n");
+        let conf = conf.to_str().unwrap();
+        let columns: Vec<String> = COLUMNS.iter().map(|c| c.to_string()).collect();
+        let mut pipelines = ColumnsPipeline::from_conf(conf, &columns).unwrap();
+        let mut expected = vec![];
+        for rec in records(2550) {
+            expected.extend(summary(&rec, &pipelines.process_record(&rec).unwrap()));
+        }
+        assert_eq!(expected[1], ("1".to_string(), Some("Rue 1".to_string())));
+        assert_eq!(expected[3], ("3".to_string(), None));
+
+        // more than 2 batches of 100 records per worker
+        for jobs in 1..5 {
+            assert_eq!(process_with_pool(conf, jobs, 2550).unwrap(), expected);
+        }
+        fs::remove_file(conf).unwrap();
+    }
+
+    #[test]
+    fn worker_error() {
+        let conf = temp_conf("error", "processes:\n  - Decode:\n      from_encoding: unknown\n");
+        let conf = conf.to_str().unwrap();
+        let err = process_with_pool(conf, 4, 1000).unwrap_err().to_string();
+        assert!(err.contains("Could not find encoding from unknown"), "{}", err);
+        fs::remove_file(conf).unwrap();
+    }
+}
@@ -1,30 +1,47 @@
 use csv;
 use std::io;
-use errors::{Result, ResultExt};
-use std::collections::HashMap;
+use errors::{ErrorKind, Result, ResultExt};
 #[derive(Debug)]
 pub struct Record {
     pub id: String,
-    pub name: String,
-    pub raw: HashMap<String, String>,
+    /// (column, name) for each column to be processed
+    pub names: Vec<(String, String)>,
+    /// values of all the columns, in the order of the headers
+    pub raw: Vec<String>,
+}
+
+/// position of the column in the headers (the first one if the headers are duplicated)
+pub fn column_position(headers: &csv::StringRecord, column: &str) -> Result<usize> {
+    headers.iter().position(|h| h == column).ok_or_else(|| {
+        ErrorKind::ColumnNotFound(column.to_string(), headers.iter().map(String::from).collect())
+            .into()
+    })
 }
 
 pub struct RecordIter<'r, R: io::Read + 'r> {
     iter: csv::StringRecordsIter<'r, R>,
-    heading_id: String,
-    heading_name: String,
-    headers: csv::StringRecord,
+    id_pos: usize,
+    /// (column, position) of the columns to be processed
+    names_pos: Vec<(String, usize)>,
 }
 
 impl<'r, R: io::Read + 'r> RecordIter<'r, R> {
-    fn new(r: &'r mut csv::Reader<R>, heading_id: &str, heading_name: &str) -> csv::Result<Self> {
+    fn new(
+        r: &'r mut csv::Reader<R>,
+        heading_id: &str,
+        heading_names: &[String],
+    ) -> Result<Self> {
         let headers = r.headers()?.clone();
+        let id_pos = column_position(&headers, heading_id)?;
+        let mut names_pos = vec![];
+        for h in heading_names {
+            names_pos.push((h.clone(), column_position(&headers, h)?));
+        }
 
         Ok(RecordIter {
             iter: r.records(),
-            heading_id: heading_id.to_string(),
-            heading_name: heading_name.to_string(),
-            headers,
+            id_pos,
+            names_pos,
         })
     }
 }
@@ -32,17 +49,22 @@ impl<'r, R: io::Read + 'r> RecordIter<'r, R> {
 impl<'r, R: io::Read + 'r> Iterator for RecordIter<'r, R> {
     type Item = csv::Result<Record>;
     fn next(&mut self) -> Option<Self::Item> {
-        fn get<'a>(record: &'a HashMap<String, String>, column: &str) -> String {
-            record[column].to_string()
+        fn get(record: &csv::StringRecord, pos: usize) -> String {
+            record.get(pos).unwrap_or_default().to_string()
         }
 
         self.iter.next().map(|r| {
-            r.and_then(|r| {
-                let rec: HashMap<String, String> = r.deserialize(Some(&self.headers))?;
-
-                let id = get(&rec, &self.heading_id);
-                let name = get(&rec, &self.heading_name);
-                Ok(Record { id, name, raw: rec })
+            r.map(|r| {
+                let id = get(&r, self.id_pos);
+                let names = self.names_pos
+                    .iter()
+                    .map(|&(ref h, pos)| (h.clone(), get(&r, pos)))
+                    .collect();
+                Record {
+                    id,
+                    names,
+                    raw: r.iter().map(String::from).collect(),
+                }
             })
         })
     }
@@ -51,13 +73,91 @@ impl<'r, R: io::Read + 'r> Iterator for RecordIter<'r, R> {
 pub fn new_record_iter<'r, R: io::Read + 'r>(
     r: &'r mut csv::Reader<R>,
     heading_id: &str,
-    heading_name: &str,
+    heading_names: &[String],
 ) -> Result<(RecordIter<'r, R>, csv::StringRecord)> {
     let headers = r.headers()
         .chain_err(|| "Can't find headers in input file")?
         .clone();
-    let rec_iter = RecordIter::new(r, heading_id, heading_name)
+    let rec_iter = RecordIter::new(r, heading_id, heading_names)
         .chain_err(|| "Can't find needed fields in the header of input file")?;
 
     Ok((rec_iter, headers))
 }
+
+#[cfg(test)]
+mod tests {
+    use csv;
+    use errors::ErrorKind;
+    use super::{column_position, new_record_iter};
+
+    const INPUT: &str = "\
+junk,stop_id,junk,stop_name,junk
+a,1,b,Gare,c
+d,2,e,Place,f
+";
+
+    fn columns(names: &[&str]) -> Vec<String> {
+        names.iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn duplicate_headers() {
+        let mut rdr = csv::Reader::from_reader(INPUT.as_bytes());
+        let (records, headers) =
+            new_record_iter(&mut rdr, "stop_id", &columns(&["stop_name", "junk"])).unwrap();
+        assert_eq!(headers.len(), 5);
+        assert_eq!(column_position(&headers, "junk").unwrap(), 0);
+        assert_eq!(column_position(&headers, "stop_name").unwrap(), 3);
+
+        let records: Vec<_> = records.map(|r| r.unwrap()).collect();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].id, "2");
+        assert_eq!(
+            records[1].names,
+            vec![
+                ("stop_name".to_string(), "Place".to_string()),
+                ("junk".to_string(), "d".to_string()),
+            ]
+        );
+        assert_eq!(records[1].raw, vec!["d", "2", "e", "Place", "f"]);
+    }
+
+    #[test]
+    fn missing_column() {
+        let mut rdr = csv::Reader::from_reader(INPUT.as_bytes());
+        let headers = rdr.headers().unwrap().clone();
+        match *column_position(&headers, "stop_desc").unwrap_err().kind() {
+            ErrorKind::ColumnNotFound(ref column, ref available) => {
+                assert_eq!(column, "stop_desc");
+                assert_eq!(available, &["junk", "stop_id", "junk", "stop_name", "junk"]);
+            }
+            ref e => panic!("unexpected error {}", e),
+        }
+
+        // the id column too
+        let cases = [("stop_id", "stop_desc", "stop_desc"), ("id", "stop_name", "id")];
+        for &(id, name, missing) in &cases {
+            let err = new_record_iter(&mut rdr, id, &columns(&[name])).err().unwrap();
+            let causes: Vec<_> = err.iter().map(|e| e.to_string()).collect();
+            let expected = format!(
+                "column {} not found (available columns: junk, stop_id, junk, stop_name, junk)",
+                missing
+            );
+            assert!(causes.contains(&expected), "{:?}", causes);
+        }
+    }
+
+    #[test]
+    fn short_records() {
+        // the fields missing from a record are empty
+        let mut rdr = csv::ReaderBuilder::new()
+            .flexible(true)
+            .from_reader("stop_name,stop_id\nGare\nPlace,2\n".as_bytes());
+        let (records, _) = new_record_iter(&mut rdr, "stop_id", &columns(&["stop_name"])).unwrap();
+        let records: Vec<_> = records.map(|r| r.unwrap()).collect();
+        assert_eq!(records[0].id, "");
+        assert_eq!(records[0].names[0].1, "Gare");
+        assert_eq!(records[0].raw, vec!["Gare"]);
+        assert_eq!(records[1].id, "2");
+    }
+}
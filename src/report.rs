@@ -0,0 +1,275 @@
+use std::cmp;
+use std::collections::BTreeMap;
+use std::io;
+use pipeline::{Modification, RecordAmbiguity, RecordOutcome, RecordRule};
+use errors::Result;
+
+const STYLE: &str = "
+body { font-family: sans-serif; }
+table { border-collapse: collapse; margin-bottom: 2em; }
+td, th { border: 1px solid #ccc; padding: 2px 8px; text-align: left; }
+del { background: #fbb; text-decoration: line-through; }
+ins { background: #bfb; text-decoration: none; }
+body.only-ambiguous tr.modified:not(.ambiguous) { display: none; }
+";
+
+struct Entry {
+    rule: Option<RecordRule>,
+    modifications: Vec<Modification>,
+    ambiguities: Vec<RecordAmbiguity>,
+}
+
+/// HTML report of the modifications, for reviewing
+#[derive(Default)]
+pub struct Report {
+    entries: Vec<Entry>,
+}
+impl Report {
+    pub fn new() -> Self {
+        Report::default()
+    }
+
+    pub fn add(&mut self, outcome: RecordOutcome) -> Result<()> {
+        if outcome.rule.is_none() && outcome.ambiguities.is_empty() {
+            return Ok(());
+        }
+        let modifications = match outcome.rule {
+            Some(ref r) => r.modifications()?,
+            None => vec![],
+        };
+        self.entries.push(Entry {
+            rule: outcome.rule,
+            modifications,
+            ambiguities: outcome.ambiguities,
+        });
+        Ok(())
+    }
+
+    /// rules are grouped by column and processor that made the change,
+    /// as each column may have its own sequence
+    /// (a rule appears in the group of each processor that changed its name)
+    pub fn write_html<W: io::Write>(&self, w: &mut W) -> Result<()> {
+        // (column, processor index, type, label) -> (entry index, modification) in this group
+        let mut groups = BTreeMap::new();
+        for (i, e) in self.entries.iter().enumerate() {
+            let column = e.rule.as_ref().map_or("", |r| r.column.as_str());
+            for m in &e.modifications {
+                groups
+                    .entry((column, m.index, m.processor.clone(), m.label.clone()))
+                    .or_insert_with(Vec::new)
+                    .push((i, m));
+            }
+        }
+        let nb_modified = self.entries.iter().filter(|e| e.rule.is_some()).count();
+        let ambiguities: Vec<&RecordAmbiguity> =
+            self.entries.iter().flat_map(|e| &e.ambiguities).collect();
+
+        writeln!(w, "<!DOCTYPE html>")?;
+        writeln!(w, "<html><head><meta charset=\"utf-8\"><title>ruspell report</title>")?;
+        writeln!(w, "<style>{}</style></head><body>", STYLE)?;
+        writeln!(w, "<h1>ruspell report</h1>")?;
+        writeln!(
+            w,
+            "<p>{} modified names, {} ambiguous spell-check suggestions</p>",
+            nb_modified,
+            ambiguities.len()
+        )?;
+        writeln!(
+            w,
+            "<p><label><input type=\"checkbox\" \
+             onchange=\"document.body.classList.toggle('only-ambiguous', this.checked)\"> \
+             Only show names with ambiguous spell-check suggestions</label></p>"
+        )?;
+
+        writeln!(w, "<h2>Modifications per column and processor</h2>")?;
+        writeln!(
+            w,
+            "<table><tr><th>Column</th><th>Processor</th><th>Modified names</th></tr>"
+        )?;
+        for (k, (g, rows)) in groups.iter().enumerate() {
+            writeln!(
+                w,
+                "<tr><td>{}</td><td><a href=\"#group-{}\">{}</a></td><td>{}</td></tr>",
+                escape(g.0),
+                k,
+                escape(&processor_title(g)),
+                rows.len()
+            )?;
+        }
+        writeln!(w, "</table>")?;
+
+        for (k, (g, rows)) in groups.iter().enumerate() {
+            writeln!(
+                w,
+                "<h2 id=\"group-{}\">{}: {} ({})</h2>",
+                k,
+                escape(g.0),
+                escape(&processor_title(g)),
+                rows.len()
+            )?;
+            writeln!(
+                w,
+                "<table><tr><th>id</th><th>column</th><th>old_name / new_name</th>\
+                 <th>change by this processor</th></tr>"
+            )?;
+            for &(i, m) in rows {
+                let e = &self.entries[i];
+                let rule = match e.rule {
+                    Some(ref r) => r,
+                    None => continue,
+                };
+                let class = if e.ambiguities.is_empty() {
+                    "modified"
+                } else {
+                    "modified ambiguous"
+                };
+                writeln!(
+                    w,
+                    "<tr class=\"{}\"><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                    class,
+                    escape(&rule.id),
+                    escape(&rule.column),
+                    char_diff(&rule.old_name, &rule.new_name),
+                    char_diff(&m.before, &m.after)
+                )?;
+            }
+            writeln!(w, "</table>")?;
+        }
+
+        writeln!(w, "<h2>Ambiguous spell-check suggestions</h2>")?;
+        writeln!(
+            w,
+            "<table><tr><th>id</th><th>column</th><th>name</th>\
+             <th>word</th><th>suggestions</th></tr>"
+        )?;
+        for a in ambiguities {
+            writeln!(
+                w,
+                "<tr class=\"ambiguous\"><td>{}</td><td>{}</td><td>{}</td>\
+                 <td>{}</td><td>{}</td></tr>",
+                escape(&a.id),
+                escape(&a.column),
+                escape(&a.name),
+                escape(&a.misspelled),
+                escape(&a.suggestions.join(", "))
+            )?;
+        }
+        writeln!(w, "</table>")?;
+        writeln!(w, "</body></html>")?;
+        Ok(())
+    }
+}
+
+fn processor_title(group: &(&str, usize, String, Option<String>)) -> String {
+    match group.3 {
+        Some(ref label) => format!("#{} {} - {}", group.1, group.2, label),
+        None => format!("#{} {}", group.1, group.2),
+    }
+}
+
+fn escape(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Diff {
+    Same,
+    Removed,
+    Added,
+}
+
+/// html of the character differences between the names
+/// (based on their longest common subsequence)
+fn char_diff(old: &str, new: &str) -> String {
+    let a: Vec<char> = old.chars().collect();
+    let b: Vec<char> = new.chars().collect();
+
+    // lcs[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if a[i] == b[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                cmp::max(lcs[i + 1][j], lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut diffs = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() || j < b.len() {
+        if i < a.len() && j < b.len() && a[i] == b[j] {
+            diffs.push((Diff::Same, a[i]));
+            i += 1;
+            j += 1;
+        } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            diffs.push((Diff::Removed, a[i]));
+            i += 1;
+        } else {
+            diffs.push((Diff::Added, b[j]));
+            j += 1;
+        }
+    }
+
+    let mut html = String::new();
+    let mut k = 0;
+    while k < diffs.len() {
+        let d = diffs[k].0;
+        let mut chunk = String::new();
+        while k < diffs.len() && diffs[k].0 == d {
+            chunk.push(diffs[k].1);
+            k += 1;
+        }
+        match d {
+            Diff::Same => html.push_str(&escape(&chunk)),
+            Diff::Removed => html.push_str(&format!("<del>{}</del>", escape(&chunk))),
+            Diff::Added => html.push_str(&format!("<ins>{}</ins>", escape(&chunk))),
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::char_diff;
+
+    #[test]
+    fn identical() {
+        assert_eq!(char_diff("Gare de Lyon", "Gare de Lyon"), "Gare de Lyon");
+        assert_eq!(char_diff("l'<gare>", "l'<gare>"), "l&#39;&lt;gare&gt;");
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(char_diff("", ""), "");
+        assert_eq!(char_diff("", "Gare"), "<ins>Gare</ins>");
+        assert_eq!(char_diff("Gare", ""), "<del>Gare</del>");
+    }
+
+    #[test]
+    fn fully_different() {
+        assert_eq!(char_diff("abc", "xyz"), "<del>abc</del><ins>xyz</ins>");
+    }
+
+    #[test]
+    fn accented() {
+        assert_eq!(char_diff("Ecole", "École"), "<del>E</del><ins>É</ins>cole");
+        assert_eq!(
+            char_diff("rue de l'ecole", "Rue de l'école"),
+            "<del>r</del><ins>R</ins>ue de l&#39;<del>e</del><ins>é</ins>cole"
+        );
+        assert_eq!(char_diff("Château", "Chateau"), "Ch<del>â</del><ins>a</ins>teau");
+    }
+}
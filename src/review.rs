@@ -0,0 +1,455 @@
+use csv;
+use regex::{self, Regex};
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use pipeline::RecordRule;
+use worker::overrides::{self, OverrideRow};
+use errors::{Result, ResultExt};
+
+/// an ambiguity of an ambiguities file
+#[derive(Debug, Deserialize)]
+pub struct AmbiguityRow {
+    pub id: String,
+    #[serde(default)]
+    pub column: String,
+    pub name: String,
+    pub misspelled: String,
+    /// separated by |
+    pub suggestions: String,
+}
+
+pub fn read_ambiguities(path: &Path) -> Result<Vec<AmbiguityRow>> {
+    let mut rdr = csv::Reader::from_path(path).chain_err(|| "Could not open ambiguities file")?;
+    let mut ambiguities = vec![];
+    for res_row in rdr.deserialize() {
+        let row: AmbiguityRow =
+            res_row.chain_err(|| format!("error at csv line decoding: {}", path.display()))?;
+        ambiguities.push(row);
+    }
+    Ok(ambiguities)
+}
+
+/// What was decided during the review
+#[derive(Debug, Default)]
+pub struct ReviewReport {
+    pub nb_accepted: usize,
+    pub nb_rejected: usize,
+    pub nb_edited: usize,
+    pub nb_skipped: usize,
+    /// items whose record was already in the overrides file
+    pub nb_already_decided: usize,
+}
+
+enum Answer {
+    Accept,
+    Reject,
+    Edit(String),
+    /// index of the chosen suggestion
+    Choice(usize),
+    Skip,
+    Quit,
+}
+
+// the overrides file, each decision being appended to it as soon as it is made,
+// and the file being written again without the replaced decisions at the end
+struct Decisions {
+    path: PathBuf,
+    rows: Vec<OverrideRow>,
+    /// index in rows of the decision for (id, column)
+    index: HashMap<(String, String), usize>,
+    appender: csv::Writer<File>,
+}
+impl Decisions {
+    fn new(path: &Path) -> Result<Self> {
+        let rows = if path.exists() {
+            overrides::read_rows(path)?
+        } else {
+            vec![]
+        };
+        let index = rows.iter()
+            .enumerate()
+            .filter(|&(_, r)| !r.id.is_empty())
+            .map(|(i, r)| ((r.id.clone(), r.column.clone()), i))
+            .collect();
+        // written again for the appended rows to follow its header
+        overrides::write_rows(path, &rows)?;
+        Ok(Decisions {
+            path: path.to_path_buf(),
+            rows,
+            index,
+            appender: overrides::append_rows(path)?,
+        })
+    }
+
+    fn get(&self, key: &(String, String)) -> Option<&str> {
+        self.index.get(key).map(|&i| self.rows[i].new_name.as_str())
+    }
+
+    fn decide(&mut self, key: (String, String), old_name: &str, new_name: &str) -> Result<()> {
+        let row = OverrideRow {
+            id: key.0.clone(),
+            column: key.1.clone(),
+            old_name: old_name.to_string(),
+            new_name: new_name.to_string(),
+        };
+        // a decision appended later replaces the previous one when the file is read
+        self.appender
+            .serialize(&row)
+            .and_then(|_| self.appender.flush().map_err(csv::Error::from))
+            .chain_err(|| format!("Could not write overrides file {}", self.path.display()))?;
+        match self.index.get(&key) {
+            Some(&i) => self.rows[i] = row,
+            None => {
+                self.index.insert(key, self.rows.len());
+                self.rows.push(row);
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<()> {
+        drop(self.appender);
+        overrides::write_rows(&self.path, &self.rows)
+    }
+}
+
+// the terminal: questions are written to output, answers read from input
+struct Terminal<R: BufRead, W: Write> {
+    input: R,
+    output: W,
+}
+impl<R: BufRead, W: Write> Terminal<R, W> {
+    // the answer line (trimmed), None at the end of the input
+    fn read_line(&mut self, question: &str) -> Result<Option<String>> {
+        write!(self.output, "{} ", question)?;
+        self.output.flush()?;
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            writeln!(self.output)?;
+            return Ok(None);
+        }
+        Ok(Some(line.trim().to_string()))
+    }
+
+    // asked again until the answer is valid, nb_choices being the number of suggestions
+    fn ask(&mut self, nb_choices: usize) -> Result<Answer> {
+        let question = match nb_choices {
+            0 => "Accept, reject, edit, skip or quit? [a/r/e/s/q]".to_string(),
+            n => format!("Choose a suggestion, reject, edit, skip or quit? [1-{}/r/e/s/q]", n),
+        };
+        loop {
+            let line = match self.read_line(&question)? {
+                Some(line) => line,
+                None => return Ok(Answer::Quit),
+            };
+            match line.as_str() {
+                "a" if nb_choices == 0 => return Ok(Answer::Accept),
+                "r" => return Ok(Answer::Reject),
+                "s" => return Ok(Answer::Skip),
+                "q" => return Ok(Answer::Quit),
+                "e" => match self.read_line("New name:")? {
+                    Some(ref name) if name.is_empty() => {
+                        writeln!(self.output, "Empty name, answer again")?
+                    }
+                    Some(name) => return Ok(Answer::Edit(name)),
+                    None => return Ok(Answer::Quit),
+                },
+                l => match l.parse::<usize>() {
+                    Ok(n) if n >= 1 && n <= nb_choices => return Ok(Answer::Choice(n - 1)),
+                    _ => writeln!(self.output, "Invalid answer {:?}", l)?,
+                },
+            }
+        }
+    }
+}
+
+// the name with the misspelled word (whole word, ignoring case) replaced by the suggestion
+fn replace_word(name: &str, misspelled: &str, suggestion: &str) -> String {
+    let re = Regex::new(&format!(r"(?i)\b{}\b", regex::escape(misspelled)))
+        .expect("escaped word is a valid regex");
+    re.replace_all(name, regex::NoExpand(suggestion)).into_owned()
+}
+
+// rules and ambiguities in the order of the review,
+// the ambiguities of a record following its rule
+enum Item<'a> {
+    Rule(&'a RecordRule),
+    Ambiguity(&'a AmbiguityRow),
+}
+
+fn review_order<'a>(rules: &'a [RecordRule], ambiguities: &'a [AmbiguityRow]) -> Vec<Item<'a>> {
+    let mut items = vec![];
+    let mut used = vec![false; ambiguities.len()];
+    for rule in rules {
+        items.push(Item::Rule(rule));
+        for (i, a) in ambiguities.iter().enumerate() {
+            if !used[i] && a.id == rule.id && a.column == rule.column {
+                items.push(Item::Ambiguity(a));
+                used[i] = true;
+            }
+        }
+    }
+    for (i, a) in ambiguities.iter().enumerate() {
+        if !used[i] {
+            items.push(Item::Ambiguity(a));
+        }
+    }
+    items
+}
+
+/// walk through the rules and ambiguities, asking for a decision on each,
+/// decisions being saved to the overrides file (to be loaded by an Overrides processor)
+/// as soon as they are made, records already in this file are not reviewed again
+pub fn review<R: BufRead, W: Write>(
+    rules: &[RecordRule],
+    ambiguities: &[AmbiguityRow],
+    overrides_path: &Path,
+    input: R,
+    output: W,
+) -> Result<ReviewReport> {
+    let mut report = ReviewReport::default();
+    let mut decisions = Decisions::new(overrides_path)?;
+    let already_decided: HashSet<_> = decisions.index.keys().cloned().collect();
+    let is_decided = |id: &str, column: &str| {
+        already_decided.contains(&(id.to_string(), column.to_string()))
+            || already_decided.contains(&(id.to_string(), String::new()))
+    };
+    let new_names: HashMap<_, _> = rules
+        .iter()
+        .map(|r| ((r.id.clone(), r.column.clone()), r.new_name.as_str()))
+        .collect();
+    let mut term = Terminal { input, output };
+
+    let items = review_order(rules, ambiguities);
+    let nb_items = items.len();
+    for (i, item) in items.into_iter().enumerate() {
+        let answer = match item {
+            Item::Rule(rule) => {
+                if is_decided(&rule.id, &rule.column) {
+                    report.nb_already_decided += 1;
+                    continue;
+                }
+                writeln!(term.output, "[{}/{}] {} of {}", i + 1, nb_items, rule.column, rule.id)?;
+                writeln!(term.output, "  old: {}", rule.old_name)?;
+                writeln!(term.output, "  new: {}", rule.new_name)?;
+                if let Ok(modifications) = rule.modifications() {
+                    let processors: Vec<_> = modifications
+                        .iter()
+                        .map(|m| m.label.as_ref().unwrap_or(&m.processor).as_str())
+                        .collect();
+                    writeln!(term.output, "  by:  {}", processors.join(", "))?;
+                }
+                let key = (rule.id.clone(), rule.column.clone());
+                let answer = term.ask(0)?;
+                match answer {
+                    Answer::Accept => decisions.decide(key, &rule.old_name, &rule.new_name)?,
+                    Answer::Reject => decisions.decide(key, &rule.old_name, &rule.old_name)?,
+                    Answer::Edit(ref name) => decisions.decide(key, &rule.old_name, name)?,
+                    _ => {}
+                }
+                answer
+            }
+            Item::Ambiguity(a) => {
+                if is_decided(&a.id, &a.column) {
+                    report.nb_already_decided += 1;
+                    continue;
+                }
+                let key = (a.id.clone(), a.column.clone());
+                // the name as it is after processing (or after the decision on its rule)
+                let name = decisions
+                    .get(&key)
+                    .or_else(|| new_names.get(&key).cloned())
+                    .unwrap_or(&a.name)
+                    .to_string();
+                let choices: Vec<_> = a.suggestions
+                    .split('|')
+                    .filter(|s| !s.is_empty())
+                    .map(|s| replace_word(&name, &a.misspelled, s))
+                    .collect();
+                writeln!(
+                    term.output,
+                    "[{}/{}] {} of {}, ambiguous word {}",
+                    i + 1,
+                    nb_items,
+                    a.column,
+                    a.id,
+                    a.misspelled
+                )?;
+                writeln!(term.output, "  name: {}", name)?;
+                for (n, choice) in choices.iter().enumerate() {
+                    writeln!(term.output, "  {}: {}", n + 1, choice)?;
+                }
+                let answer = if choices.is_empty() {
+                    term.ask(0)?
+                } else {
+                    term.ask(choices.len())?
+                };
+                match answer {
+                    Answer::Accept | Answer::Reject => decisions.decide(key, &a.name, &name)?,
+                    Answer::Edit(ref new_name) => decisions.decide(key, &a.name, new_name)?,
+                    Answer::Choice(n) => decisions.decide(key, &a.name, &choices[n])?,
+                    _ => {}
+                }
+                answer
+            }
+        };
+        match answer {
+            Answer::Accept | Answer::Choice(_) => report.nb_accepted += 1,
+            Answer::Reject => report.nb_rejected += 1,
+            Answer::Edit(_) => report.nb_edited += 1,
+            Answer::Skip => report.nb_skipped += 1,
+            Answer::Quit => break,
+        }
+    }
+    decisions.finish()?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+    use std::{env, fs, process};
+    use pipeline::RecordRule;
+    use worker::overrides;
+    use super::{review, AmbiguityRow, ReviewReport};
+
+    // a path in the temporary directory, without file
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("ruspell-review-{}-{}.csv", process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn rule(id: &str, old_name: &str, new_name: &str) -> RecordRule {
+        RecordRule {
+            id: id.to_string(),
+            column: "stop_name".to_string(),
+            old_name: old_name.to_string(),
+            new_name: new_name.to_string(),
+            debug: String::new(),
+            trace: String::new(),
+        }
+    }
+
+    fn ambiguity(id: &str, name: &str, misspelled: &str, suggestions: &str) -> AmbiguityRow {
+        AmbiguityRow {
+            id: id.to_string(),
+            column: "stop_name".to_string(),
+            name: name.to_string(),
+            misspelled: misspelled.to_string(),
+            suggestions: suggestions.to_string(),
+        }
+    }
+
+    // the report and the output of the review answered by the lines of answers
+    fn run(
+        rules: &[RecordRule],
+        ambiguities: &[AmbiguityRow],
+        path: &Path,
+        answers: &str,
+    ) -> (ReviewReport, String) {
+        let mut output = vec![];
+        let report = review(rules, ambiguities, path, answers.as_bytes(), &mut output).unwrap();
+        (report, String::from_utf8(output).unwrap())
+    }
+
+    // (id, old_name, new_name) of the rows of the overrides file
+    fn decisions(path: &Path) -> Vec<(String, String, String)> {
+        overrides::read_rows(path)
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.id, r.old_name, r.new_name))
+            .collect()
+    }
+
+    fn decision(id: &str, old_name: &str, new_name: &str) -> (String, String, String) {
+        (id.to_string(), old_name.to_string(), new_name.to_string())
+    }
+
+    #[test]
+    fn rules_answers() {
+        let path = temp_path("rules_answers");
+        let rules = [
+            rule("1", "st denis", "St Denis"),
+            rule("2", "gare", "Gare"),
+            rule("3", "mairie", "Mairie"),
+            rule("4", "eglise", "Eglise"),
+        ];
+        let (report, _) = run(&rules, &[], &path, "a\nr\ne\nHôtel de Ville\ns\n");
+        assert_eq!(
+            (report.nb_accepted, report.nb_rejected, report.nb_edited, report.nb_skipped),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(
+            decisions(&path),
+            vec![
+                decision("1", "st denis", "St Denis"),
+                decision("2", "gare", "gare"),
+                decision("3", "mairie", "Hôtel de Ville"),
+            ]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn invalid_answers() {
+        let path = temp_path("invalid_answers");
+        let rules = [rule("1", "gare", "Gare")];
+        let (report, output) = run(&rules, &[], &path, "x\n1\ne\n\na\n");
+        assert_eq!(report.nb_accepted, 1);
+        assert!(output.contains("Invalid answer \"x\""));
+        assert!(output.contains("Invalid answer \"1\""));
+        assert!(output.contains("Empty name, answer again"));
+        assert_eq!(decisions(&path), vec![decision("1", "gare", "Gare")]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn suggestion_choice() {
+        let path = temp_path("suggestion_choice");
+        let ambiguities = [ambiguity("1", "St Denis", "St", "Saint|Sainte")];
+        let (report, output) = run(&[], &ambiguities, &path, "3\na\n2\n");
+        assert_eq!(report.nb_accepted, 1);
+        assert!(output.contains("  2: Sainte Denis"));
+        assert!(output.contains("Invalid answer \"3\""));
+        assert!(output.contains("Invalid answer \"a\""));
+        assert_eq!(decisions(&path), vec![decision("1", "St Denis", "Sainte Denis")]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn later_decision_replaces() {
+        let path = temp_path("later_decision_replaces");
+        let rules = [rule("1", "st denis", "St Denis")];
+        let ambiguities = [ambiguity("1", "st denis", "St", "Saint")];
+        // the ambiguity is reviewed on the name accepted for the rule
+        let (_, output) = run(&rules, &ambiguities, &path, "a\n1\n");
+        assert!(output.contains("  name: St Denis"));
+        assert_eq!(decisions(&path), vec![decision("1", "st denis", "Saint Denis")]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn quit_and_resume() {
+        let path = temp_path("quit_and_resume");
+        let rules = [rule("1", "gare", "Gare"), rule("2", "mairie", "Mairie")];
+        let (report, _) = run(&rules, &[], &path, "a\nq\n");
+        assert_eq!((report.nb_accepted, report.nb_skipped), (1, 0));
+        assert_eq!(decisions(&path), vec![decision("1", "gare", "Gare")]);
+
+        // the end of the input quits too
+        let (report, output) = run(&rules, &[], &path, "");
+        assert_eq!((report.nb_already_decided, report.nb_accepted), (1, 0));
+        assert!(!output.contains("of 1"));
+
+        let (report, _) = run(&rules, &[], &path, "r\n");
+        assert_eq!((report.nb_already_decided, report.nb_rejected), (1, 1));
+        assert_eq!(
+            decisions(&path),
+            vec![decision("1", "gare", "Gare"), decision("2", "mairie", "mairie")]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
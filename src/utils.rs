@@ -64,6 +64,17 @@ pub fn snake_case(name: &str) -> String {
     new_name
 }
 
+/// the form with the case of the word (uppercase, capitalized, or as is)
+pub fn with_case_of(form: &str, word: &str) -> String {
+    if word.chars().count() > 1 && word.chars().all(|c| !c.is_lowercase()) {
+        form.to_uppercase()
+    } else if word.chars().next().is_some_and(char::is_uppercase) {
+        first_upper(form)
+    } else {
+        form.to_string()
+    }
+}
+
 // normalize a word (remove accents, lowercase, ...)
 pub fn normed(word: &str) -> String {
     word.nfkd()
@@ -0,0 +1,391 @@
+use encoding::label::encoding_from_whatwg_label;
+use encoding::{EncoderTrap, EncodingRef};
+use std::io::{BufRead, BufReader, Read, Write};
+use errors::{Result, ResultExt};
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    StartField,
+    Unquoted,
+    Quoted,
+    QuoteInQuoted,
+}
+
+// a record as found in the file (with the blank lines before it, ignored by csv readers),
+// and the byte span of each of its fields (quotes included), with whether it is quoted
+struct RawRecord {
+    bytes: Vec<u8>,
+    fields: Vec<(usize, usize, bool)>,
+}
+
+// a record being split, byte after byte
+struct Splitter {
+    record: RawRecord,
+    field_start: usize,
+    quoted: bool,
+    state: State,
+    // end of the line ended by the last byte, a '\r' possibly followed by a '\n'
+    cr_end: Option<usize>,
+}
+impl Splitter {
+    fn new() -> Self {
+        Splitter {
+            record: RawRecord {
+                bytes: vec![],
+                fields: vec![],
+            },
+            field_start: 0,
+            quoted: false,
+            state: State::StartField,
+            cr_end: None,
+        }
+    }
+
+    // true if the line ending at end ends the record (blank lines being part of the next one)
+    fn end_line(&mut self, end: usize) -> bool {
+        if self.record.fields.is_empty() && self.state == State::StartField {
+            // blank line
+            self.field_start = self.record.bytes.len();
+            return false;
+        }
+        self.record.fields.push((self.field_start, end, self.quoted));
+        true
+    }
+
+    // (whether the byte is part of the record, whether the record is complete)
+    fn push(&mut self, b: u8, delimiter: u8) -> (bool, bool) {
+        if let Some(end) = self.cr_end.take() {
+            if b == b'\n' {
+                self.record.bytes.push(b);
+                return (true, self.end_line(end));
+            }
+            if self.end_line(end) {
+                return (false, true);
+            }
+        }
+        self.record.bytes.push(b);
+        let pos = self.record.bytes.len() - 1;
+        match (self.state, b) {
+            (State::Quoted, b'"') => self.state = State::QuoteInQuoted,
+            (State::Quoted, _) => {}
+            (_, b'\r') => self.cr_end = Some(pos),
+            (_, b'\n') => return (true, self.end_line(pos)),
+            (_, d) if d == delimiter => {
+                self.record.fields.push((self.field_start, pos, self.quoted));
+                self.field_start = pos + 1;
+                self.quoted = false;
+                self.state = State::StartField;
+            }
+            (State::StartField, b'"') => {
+                self.quoted = true;
+                self.state = State::Quoted;
+            }
+            (State::QuoteInQuoted, b'"') => self.state = State::Quoted,
+            _ => self.state = State::Unquoted,
+        }
+        (true, false)
+    }
+
+    // the record at the end of the file, None if there is no byte left
+    fn finish(mut self) -> Option<RawRecord> {
+        if let Some(end) = self.cr_end.take() {
+            if self.end_line(end) {
+                return Some(self.record);
+            }
+        }
+        if !self.record.fields.is_empty() || self.state != State::StartField {
+            let end = self.record.bytes.len();
+            self.record.fields.push((self.field_start, end, self.quoted));
+        }
+        if self.record.bytes.is_empty() {
+            None
+        } else {
+            Some(self.record)
+        }
+    }
+}
+
+// records of a csv file, split like csv readers do
+struct RawRecords<R: Read> {
+    rdr: BufReader<R>,
+    delimiter: u8,
+}
+impl<R: Read> RawRecords<R> {
+    fn new(rdr: R, delimiter: u8) -> Self {
+        RawRecords {
+            rdr: BufReader::new(rdr),
+            delimiter,
+        }
+    }
+
+    // the next record, None at the end of the file
+    // (a record without field is made of the blank lines at the end of the file)
+    fn next_record(&mut self) -> Result<Option<RawRecord>> {
+        let mut splitter = Splitter::new();
+        loop {
+            let (nb_used, complete) = {
+                let buf = self.rdr.fill_buf()?;
+                if buf.is_empty() {
+                    return Ok(splitter.finish());
+                }
+                let mut nb_used = 0;
+                let mut complete = false;
+                for &b in buf {
+                    let (used, end) = splitter.push(b, self.delimiter);
+                    if used {
+                        nb_used += 1;
+                    }
+                    if end {
+                        complete = true;
+                        break;
+                    }
+                }
+                (nb_used, complete)
+            };
+            self.rdr.consume(nb_used);
+            if complete {
+                return Ok(Some(splitter.record));
+            }
+        }
+    }
+}
+
+/// writer copying the records of the input verbatim (quoting, line endings, byte order mark),
+/// but for the changed fields, rewritten with the quoting of the input
+pub struct VerbatimWriter<W: Write> {
+    records: RawRecords<Box<dyn Read>>,
+    wtr: W,
+    delimiter: u8,
+    encoding: EncodingRef,
+}
+impl<W: Write> VerbatimWriter<W> {
+    /// input: the raw input (decompressed but not decoded), with its delimiter and encoding
+    pub fn new(input: Box<dyn Read>, wtr: W, delimiter: u8, encoding: &str) -> Result<Self> {
+        let encoding = encoding_from_whatwg_label(encoding)
+            .ok_or_else(|| format!("Could not find encoding from {}", encoding))?;
+        if encoding.name().starts_with("utf-16") {
+            bail!("The format of {} input can't be preserved", encoding.name());
+        }
+        Ok(VerbatimWriter {
+            records: RawRecords::new(input, delimiter),
+            wtr,
+            delimiter,
+            encoding,
+        })
+    }
+
+    fn next_record(&mut self) -> Result<RawRecord> {
+        self.records
+            .next_record()?
+            .ok_or_else(|| "Could not find record in input".into())
+    }
+
+    /// copy the header line
+    pub fn write_headers(&mut self) -> Result<()> {
+        let record = self.next_record()?;
+        self.wtr.write_all(&record.bytes)?;
+        Ok(())
+    }
+
+    /// copy the next record, the given fields (index, value) being replaced,
+    /// nb_fields being the number of fields of the record read by the csv reader
+    /// (both readers must agree, or the new names would be written in the wrong place)
+    pub fn write_record(&mut self, nb_fields: usize, changes: &[(usize, String)]) -> Result<()> {
+        let record = self.next_record()?;
+        if record.fields.len() != nb_fields {
+            bail!(
+                "The format of the input can't be preserved: a record of {} fields \
+                 is read as {} fields",
+                nb_fields,
+                record.fields.len()
+            );
+        }
+        if changes.is_empty() {
+            self.wtr.write_all(&record.bytes)?;
+            return Ok(());
+        }
+        let mut pos = 0;
+        for (i, &(start, end, quoted)) in record.fields.iter().enumerate() {
+            if let Some((_, value)) = changes.iter().find(|c| c.0 == i) {
+                self.wtr.write_all(&record.bytes[pos..start])?;
+                let field = self.encode_field(value, quoted)?;
+                self.wtr.write_all(&field)?;
+                pos = end;
+            }
+        }
+        self.wtr.write_all(&record.bytes[pos..])?;
+        Ok(())
+    }
+
+    // the value in the encoding of the input, quoted if the input field was or if needed
+    fn encode_field(&self, value: &str, quoted: bool) -> Result<Vec<u8>> {
+        let needs_quotes = value
+            .bytes()
+            .any(|b| b == self.delimiter || b == b'"' || b == b'\r' || b == b'\n');
+        let field = if quoted || needs_quotes {
+            format!("\"{}\"", value.replace('"', "\"\""))
+        } else {
+            value.to_string()
+        };
+        self.encoding
+            .encode(&field, EncoderTrap::Strict)
+            .map_err(|_| format!("Could not encode {} as {}", value, self.encoding.name()).into())
+    }
+
+    /// the output
+    pub fn into_inner(self) -> W {
+        self.wtr
+    }
+
+    /// copy the end of the input (blank lines) and flush
+    pub fn finish(&mut self) -> Result<()> {
+        while let Some(record) = self.records.next_record()? {
+            if !record.fields.is_empty() {
+                bail!("The format of the input can't be preserved: more records than read");
+            }
+            self.wtr.write_all(&record.bytes)?;
+        }
+        self.wtr.flush().chain_err(|| "Could not write into output file")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use csv;
+    use std::io::{self, Cursor, Read};
+    use super::{RawRecords, VerbatimWriter};
+    use errors::Result;
+
+    // a reader giving one byte at a time
+    struct ByteReader(Cursor<Vec<u8>>);
+    impl Read for ByteReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let len = buf.len().min(1);
+            self.0.read(&mut buf[..len])
+        }
+    }
+
+    // (start, end, quoted) of the fields of a record
+    type Fields = Vec<(usize, usize, bool)>;
+
+    // (bytes, fields) of the records of the input
+    fn split<R: Read>(rdr: R) -> Vec<(Vec<u8>, Fields)> {
+        let mut records = RawRecords::new(rdr, b',');
+        let mut split = vec![];
+        while let Some(r) = records.next_record().unwrap() {
+            split.push((r.bytes, r.fields));
+        }
+        split
+    }
+
+    // the input written by a VerbatimWriter driven by a csv reader,
+    // with the changes (record index, field index, value)
+    fn rewrite(input: &[u8], changes: &[(usize, usize, &str)]) -> Result<Vec<u8>> {
+        let bom = if input.starts_with(b"\xef\xbb\xbf") { 3 } else { 0 };
+        let mut rdr = csv::Reader::from_reader(&input[bom..]);
+        let mut out = vec![];
+        {
+            let raw = Box::new(Cursor::new(input.to_vec()));
+            let mut wtr = VerbatimWriter::new(raw, &mut out, b',', "utf-8")?;
+            wtr.write_headers()?;
+            for (i, rec) in rdr.records().enumerate() {
+                let rec = rec?;
+                let rec_changes: Vec<_> = changes
+                    .iter()
+                    .filter(|c| c.0 == i)
+                    .map(|c| (c.1, c.2.to_string()))
+                    .collect();
+                wtr.write_record(rec.len(), &rec_changes)?;
+            }
+            wtr.finish()?;
+        }
+        Ok(out)
+    }
+
+    fn assert_identical(input: &[u8]) {
+        assert_eq!(rewrite(input, &[]).unwrap(), input.to_vec());
+    }
+
+    #[test]
+    fn simple() {
+        assert_identical(b"id,name\n1,a\n2,b\n");
+    }
+
+    #[test]
+    fn bom_and_crlf() {
+        assert_identical(b"\xef\xbb\xbfid,name\r\n1,a\r\n2,b\r\n");
+    }
+
+    #[test]
+    fn no_final_newline() {
+        assert_identical(b"id,name\n1,a\n2,b");
+        assert_identical(b"id,name\n1,a\n2,");
+    }
+
+    #[test]
+    fn blank_lines() {
+        assert_identical(b"id,name\n\n1,a\n\r\n\n2,b\n\n\n");
+    }
+
+    #[test]
+    fn quoted_fields() {
+        assert_identical(b"id,name\n1,\"a,\"\"b\"\"\"\n2,\"line\r\nbreak\n\"\n3,\"\"\n");
+    }
+
+    #[test]
+    fn stray_quotes() {
+        assert_identical(b"id,name\n1,a\"b\n2,\"a\"b\n3,x\n");
+    }
+
+    #[test]
+    fn changed_fields() {
+        let input = b"id,name,desc\r\n1,a,\"x\"\r\n2,\"b\",y\r\n3,c,z";
+        let out = rewrite(input, &[(0, 2, "X"), (1, 1, "b,B"), (2, 1, "C")]).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "id,name,desc\r\n1,a,\"X\"\r\n2,\"b,B\",y\r\n3,C,z"
+        );
+    }
+
+    #[test]
+    fn more_raw_records() {
+        let mut rdr = csv::Reader::from_reader(&b"id,name\n1,a\n"[..]);
+        let mut out = vec![];
+        let raw = Box::new(Cursor::new(b"id,name\n1,a\n2,b\n".to_vec()));
+        let mut wtr = VerbatimWriter::new(raw, &mut out, b',', "utf-8").unwrap();
+        wtr.write_headers().unwrap();
+        for rec in rdr.records() {
+            wtr.write_record(rec.unwrap().len(), &[]).unwrap();
+        }
+        assert!(wtr.finish().is_err());
+    }
+
+    #[test]
+    fn different_fields() {
+        let raw = Box::new(Cursor::new(b"id,name\n1,a\n".to_vec()));
+        let mut out = vec![];
+        let mut wtr = VerbatimWriter::new(raw, &mut out, b',', "utf-8").unwrap();
+        wtr.write_headers().unwrap();
+        assert!(wtr.write_record(3, &[]).is_err());
+    }
+
+    #[test]
+    fn split_across_reads() {
+        let inputs: &[&[u8]] = &[
+            b"id,name\r\n\r\n1,\"a\r\nb\"\r\n2,c\r",
+            b"id,name\r\r\n1,a\n\n",
+            b"id,\"name\"\"\"\n1,",
+        ];
+        for input in inputs {
+            let whole = split(Cursor::new(input.to_vec()));
+            assert_eq!(split(ByteReader(Cursor::new(input.to_vec()))), whole);
+            let bytes: Vec<u8> = whole.iter().flat_map(|r| r.0.clone()).collect();
+            assert_eq!(&bytes, input);
+        }
+        let records = split(Cursor::new(inputs[0].to_vec()));
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].0, b"\r\n1,\"a\r\nb\"\r\n".to_vec());
+        assert_eq!(records[1].1, vec![(2, 3, false), (4, 10, true)]);
+        assert_eq!(records[2].1, vec![(0, 1, false), (2, 3, false)]);
+    }
+}
@@ -0,0 +1,284 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use utils;
+use super::bano_reader::{self, CorpusFile, Thresholds};
+use super::dictionary::Dictionary;
+use super::ispell_wrapper::Ambiguity;
+use errors::Result;
+
+enum Restoration {
+    Form(String),
+    Ambiguous(Vec<String>),
+}
+
+/// restore the accents of words, using an index of the accented forms
+/// of dictionaries and BANO corpora by normed form
+/// (and of the names of the input if given with add_input_corpus)
+pub struct AccentRestore {
+    // normed form -> lowercase form -> occurences in BANO and input
+    forms: BTreeMap<String, BTreeMap<String, u32>>,
+    // normed form -> restoration of the words with this normed form (and no accent)
+    index: HashMap<String, Restoration>,
+    thresholds: Thresholds,
+    input_columns: Vec<String>,
+    ambiguities: Vec<Ambiguity>,
+    word_choices: HashMap<String, String>,
+}
+impl AccentRestore {
+    /// dictionaries are Hunspell files if ending with .dic, word lists otherwise,
+    /// the words of the corpora being selected with the thresholds
+    /// (the frequency is not used, only the dominance of a form)
+    pub fn new(
+        dictionaries: &[String],
+        bano_files: &[CorpusFile],
+        conf_path: &Path,
+        thresholds: Thresholds,
+    ) -> Result<Self> {
+        let mut forms: BTreeMap<String, BTreeMap<String, u32>> = BTreeMap::new();
+        for d in dictionaries {
+            let path = conf_path.join(d);
+            let dict = if d.ends_with(".dic") {
+                Dictionary::from_hunspell(&path)?
+            } else {
+                Dictionary::from_word_list(&path)?
+            };
+            for f in dict.forms() {
+                forms
+                    .entry(utils::normed(f))
+                    .or_default()
+                    .entry(f.to_lowercase())
+                    .or_insert(0);
+            }
+        }
+        add_counts(&mut forms, bano_reader::read_words(bano_files, conf_path, &thresholds)?);
+
+        let index = build_index(&forms, thresholds.dominance_ratio);
+        Ok(AccentRestore {
+            forms,
+            index,
+            thresholds,
+            input_columns: vec![],
+            ambiguities: vec![],
+            word_choices: HashMap::new(),
+        })
+    }
+
+    /// columns of the input whose names are used as corpus, see add_input_corpus
+    pub fn set_input_columns(&mut self, columns: Vec<String>) {
+        self.input_columns = columns;
+    }
+
+    /// add the words of the names of the input columns to the corpus,
+    /// the dominant forms of the input then fixing the other ones
+    pub fn add_input_corpus(&mut self, names: &[(String, String)]) {
+        let mut input_words = BTreeMap::new();
+        for (column, name) in names {
+            if self.input_columns.contains(column) {
+                bano_reader::add_words(&mut input_words, name, &self.thresholds);
+            }
+        }
+        let mut input_forms = BTreeMap::new();
+        add_counts(&mut input_forms, input_words);
+        for (normed, map) in &input_forms {
+            if map.len() < 2 {
+                continue;
+            }
+            let votes: Vec<String> = map.iter().map(|(f, c)| format!("{} ({})", f, c)).collect();
+            match bano_reader::get_interesting_word(map, self.thresholds.dominance_ratio) {
+                Some(w) => info!("Input votes for {}: {} -> {}", normed, votes.join(", "), w),
+                None => info!("Input votes for {}: {}, no majority", normed, votes.join(", ")),
+            }
+        }
+        for (normed, map) in input_forms {
+            let entry = self.forms.entry(normed).or_default();
+            for (form, count) in map {
+                *entry.entry(form).or_insert(0) += count;
+            }
+        }
+        self.index = build_index(&self.forms, self.thresholds.dominance_ratio);
+    }
+
+    /// words chosen by hand for ambiguous words, used instead of the index
+    pub fn set_word_choices(&mut self, word_choices: HashMap<String, String>) {
+        self.word_choices = word_choices;
+    }
+
+    /// ambiguities met since last call
+    pub fn take_ambiguities(&mut self) -> Vec<Ambiguity> {
+        ::std::mem::take(&mut self.ambiguities)
+    }
+
+    pub fn process(&mut self, name: &str) -> String {
+        let mut new_name = String::with_capacity(name.len());
+        for w in utils::get_words(name) {
+            if let Some(choice) = self.word_choices.get(w) {
+                new_name.push_str(choice);
+                continue;
+            }
+            if utils::has_accent(w) {
+                new_name.push_str(w);
+                continue;
+            }
+            match self.index.get(&utils::normed(w)) {
+                Some(Restoration::Form(f)) => new_name.push_str(&utils::with_case_of(f, w)),
+                Some(Restoration::Ambiguous(forms)) => {
+                    info!("Ambiguous accented forms for {} : {:?}", w, forms);
+                    self.ambiguities.push(Ambiguity {
+                        misspelled: w.to_string(),
+                        suggestions: forms.iter().map(|f| utils::with_case_of(f, w)).collect(),
+                    });
+                    new_name.push_str(w);
+                }
+                None => new_name.push_str(w),
+            }
+        }
+        new_name
+    }
+}
+
+// add the occurences of the words of the forms, by lowercase form
+fn add_counts(
+    forms: &mut BTreeMap<String, BTreeMap<String, u32>>,
+    words: BTreeMap<String, BTreeMap<String, u32>>,
+) {
+    for (_, map) in words {
+        for (form, count) in map {
+            for w in utils::get_words(&form) {
+                if !w.chars().any(char::is_alphabetic) {
+                    continue;
+                }
+                *forms
+                    .entry(utils::normed(w))
+                    .or_default()
+                    .entry(w.to_lowercase())
+                    .or_insert(0) += count;
+            }
+        }
+    }
+}
+
+fn build_index(
+    forms: &BTreeMap<String, BTreeMap<String, u32>>,
+    dominance_ratio: u32,
+) -> HashMap<String, Restoration> {
+    let index: HashMap<_, _> = forms
+        .iter()
+        .filter_map(|(normed, map)| {
+            restoration(normed, map, dominance_ratio).map(|r| (normed.clone(), r))
+        })
+        .collect();
+    let nb_ambiguous = index
+        .values()
+        .filter(|r| matches!(r, Restoration::Ambiguous(_)))
+        .count();
+    info!(
+        "Accent index: {} restorable words, {} ambiguous words",
+        index.len() - nb_ambiguous,
+        nb_ambiguous
+    );
+    index
+}
+
+// the form restoring the accents of the words with this normed form:
+// the dominant form in BANO, else the only accented form if the word
+// without accent is not a valid form itself
+fn restoration(
+    normed: &str,
+    forms: &BTreeMap<String, u32>,
+    dominance_ratio: u32,
+) -> Option<Restoration> {
+    let seen: BTreeMap<String, u32> = forms
+        .iter()
+        .filter(|&(_, c)| *c > 0)
+        .map(|(f, c)| (f.clone(), *c))
+        .collect();
+    if !seen.is_empty() {
+        if let Some(f) = bano_reader::get_interesting_word(&seen, dominance_ratio) {
+            return if f != normed && utils::has_accent(&f) {
+                Some(Restoration::Form(f))
+            } else {
+                None
+            };
+        }
+    }
+    if forms.contains_key(normed) {
+        return None;
+    }
+    let accented: Vec<String> = forms
+        .keys()
+        .filter(|f| utils::has_accent(f))
+        .cloned()
+        .collect();
+    match accented.len() {
+        0 => None,
+        1 => accented.into_iter().next().map(Restoration::Form),
+        _ => Some(Restoration::Ambiguous(accented)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::{env, fs, process, slice};
+    use worker::bano_reader::Thresholds;
+    use super::AccentRestore;
+
+    // a restorer of the words of the word list
+    fn restore(name: &str, words: &str) -> AccentRestore {
+        let file = format!("ruspell-accents-{}-{}.txt", process::id(), name);
+        let dir = env::temp_dir();
+        fs::write(dir.join(&file), words).unwrap();
+        let restore = AccentRestore::new(slice::from_ref(&file), &[], &dir, Thresholds::default());
+        fs::remove_file(dir.join(&file)).unwrap();
+        restore.unwrap()
+    }
+
+    #[test]
+    fn restored() {
+        let mut r = restore("restored", "école\ngare\n");
+        assert_eq!(r.process("rue de l'ecole"), "rue de l'école");
+        assert_eq!(r.process("gare"), "gare");
+        assert!(r.take_ambiguities().is_empty());
+    }
+
+    #[test]
+    fn case_kept() {
+        let mut r = restore("case_kept", "école\n");
+        assert_eq!(r.process("Ecole"), "École");
+        assert_eq!(r.process("ECOLE"), "ÉCOLE");
+        assert_eq!(r.process("Rue de l'Ecole"), "Rue de l'École");
+    }
+
+    #[test]
+    fn ambiguous() {
+        let mut r = restore("ambiguous", "pâte\npâté\ncôte\ncote\n");
+        assert_eq!(r.process("Pate"), "Pate");
+        let ambiguities = r.take_ambiguities();
+        assert_eq!(ambiguities.len(), 1);
+        assert_eq!(ambiguities[0].misspelled, "Pate");
+        assert_eq!(ambiguities[0].suggestions, vec!["Pâte", "Pâté"]);
+        // the word without accent is valid
+        assert_eq!(r.process("cote"), "cote");
+        assert!(r.take_ambiguities().is_empty());
+
+        let mut choices = HashMap::new();
+        choices.insert("Pate".to_string(), "Pâté".to_string());
+        r.set_word_choices(choices);
+        assert_eq!(r.process("Pate"), "Pâté");
+        assert!(r.take_ambiguities().is_empty());
+    }
+
+    #[test]
+    fn input_corpus() {
+        let mut r = restore("input_corpus", "pâte\npâté\n");
+        r.set_input_columns(vec!["stop_name".to_string()]);
+        let names: Vec<_> = ["Pâté", "Pâté", "Pâté", "Pâté", "Pate", "Pâte"]
+            .iter()
+            .map(|n| ("stop_name".to_string(), n.to_string()))
+            .chain(Some(("stop_desc".to_string(), "Pâte".to_string())))
+            .collect();
+        r.add_input_corpus(&names);
+        assert_eq!(r.process("pate"), "pâté");
+        assert!(r.take_ambiguities().is_empty());
+    }
+}
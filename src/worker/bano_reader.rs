@@ -1,69 +1,479 @@
 use std::collections::BTreeMap;
+use std::fs;
 use std::io;
+use std::str::FromStr;
 use csv;
+use files;
+use records_reader;
 use utils;
 use super::ispell_wrapper::SpellCheck;
+use super::spell_cache::{self, CacheKey, SharedCaches};
+use std::path::PathBuf;
 use errors::{ErrorKind, Result, ResultExt};
 use std::path::Path;
 
-pub fn populate_dict_from_files(
-    files: &[String],
-    ispell: &mut SpellCheck,
+/// column of a corpus file, by index (starting at 0) or by header name
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+impl FromStr for Column {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        Ok(match s.parse() {
+            Ok(i) => Column::Index(i),
+            Err(_) => Column::Name(s.to_string()),
+        })
+    }
+}
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_columns() -> Vec<Column> {
+    // street and city of BANO
+    vec![Column::Index(2), Column::Index(4)]
+}
+
+/// a csv file of street and city names (BANO format by default),
+/// the words of its columns being used as corpus
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CorpusFile {
+    pub file: String,
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    #[serde(default)]
+    pub has_headers: bool,
+    /// columns containing words
+    #[serde(default = "default_columns")]
+    pub columns: Vec<Column>,
+}
+impl CorpusFile {
+    /// a BANO file
+    pub fn bano(file: &str) -> Self {
+        CorpusFile {
+            file: file.to_string(),
+            delimiter: default_delimiter(),
+            has_headers: false,
+            columns: default_columns(),
+        }
+    }
+}
+
+/// thresholds of the selection of the words of the corpus added to the dictionary
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Thresholds {
+    /// a dominant form is added if it appears at least corpus_size / frequency_divisor times
+    /// (or if the dictionary has no word with the same normed form)
+    pub frequency_divisor: u32,
+    /// a form is dominant if it appears dominance_ratio times more than any other one
+    pub dominance_ratio: u32,
+    /// full-uppercase words are not considered
+    pub skip_uppercase: bool,
+    /// words containing a digit are not considered
+    pub skip_digits: bool,
+}
+impl Default for Thresholds {
+    fn default() -> Self {
+        Thresholds {
+            frequency_divisor: 100_000,
+            dominance_ratio: 4, // empirical
+            skip_uppercase: true,
+            skip_digits: true,
+        }
+    }
+}
+
+/// decision about the words of the corpus with the same normed form
+#[derive(Debug, Serialize)]
+pub struct Candidate {
+    pub normed: String,
+    /// forms of the corpus, separated by spaces
+    pub variants: String,
+    /// occurences of each form, separated by spaces
+    pub counts: String,
+    /// dominant form, if any
+    pub word: Option<String>,
+    /// added, rejected or skipped (forms not considered, see Thresholds)
+    pub decision: &'static str,
+    pub reason: &'static str,
+}
+
+impl Candidate {
+    fn new(
+        normed: &str,
+        map: &BTreeMap<String, u32>,
+        word: Option<String>,
+        decision: &'static str,
+        reason: &'static str,
+    ) -> Self {
+        Candidate {
+            normed: normed.to_string(),
+            variants: map.keys().cloned().collect::<Vec<_>>().join(" "),
+            counts: map.values()
+                .map(|c| c.to_string())
+                .collect::<Vec<_>>()
+                .join(" "),
+            word,
+            decision,
+            reason,
+        }
+    }
+}
+
+/// hash of the contents and formats of the files
+fn hash_corpus(files: &[CorpusFile], conf_path: &Path) -> Result<spell_cache::Hash> {
+    let paths: Vec<_> = files.iter().map(|f| conf_path.join(&f.file)).collect();
+    let mut hash = spell_cache::hash_files(&paths)?;
+    for f in files {
+        hash.write(format!("{:?}", f).as_bytes());
+    }
+    Ok(hash)
+}
+
+/// occurences of the forms of the words of street and city names, by normed form
+pub fn read_words(
+    files: &[CorpusFile],
     conf_path: &Path,
-) -> Result<()> {
+    thresholds: &Thresholds,
+) -> Result<BTreeMap<String, BTreeMap<String, u32>>> {
     // This map is built as follows :
     // map_normed["napoleon"] = map_napo
     // map_napo["Napoléon"] = 42 (occurences)
     // map_napo["Napoleon"] = 2 (occurences)
     let mut map_normed = BTreeMap::new();
     for f in files {
-        let mut file_path = conf_path.join(f);
+        let mut file_path = conf_path.join(&f.file);
         file_path = file_path
             .canonicalize()
             .chain_err(|| format!("Could not read {}", file_path.display()))?;
-        println!("Reading street and city names from {}", file_path.display());
+        info!("Reading street and city names from {}", file_path.display());
 
+        if !f.delimiter.is_ascii() {
+            bail!(
+                "Invalid delimiter {:?} of {} (an ASCII character is needed)",
+                f.delimiter,
+                file_path.display()
+            );
+        }
         let mut rdr = csv::ReaderBuilder::new()
-            .has_headers(false)
-            .from_path(&file_path)
-            .chain_err(|| format!("Could not open BANO file {}", file_path.display()))?;
-        let banos = BanoIter::new(&mut rdr);
+            .has_headers(f.has_headers)
+            .delimiter(f.delimiter as u8)
+            .from_reader(
+                files::open(&file_path)
+                    .chain_err(|| format!("Could not open BANO file {}", file_path.display()))?,
+            );
+        let banos = CorpusIter::new(&mut rdr, &f.columns, f.has_headers)
+            .chain_err(|| format!("Could not read columns of {}", file_path.display()))?;
 
         for res_b in banos {
             let b =
                 res_b.chain_err(|| format!("error at line csv decoding: {}", file_path.display()))?;
-            for w in b.street.split_whitespace().chain(b.city.split_whitespace()) {
-                // do not consider full-uppercase word or word containing a digit
-                if w.chars().all(|c| !c.is_lowercase()) || w.chars().any(|c| c.is_numeric()) {
-                    continue;
-                }
-                let map = map_normed
-                    .entry(utils::normed(w))
-                    .or_insert_with(BTreeMap::new);
-                *map.entry(w.to_string()).or_insert(0) += 1;
+            for v in &b {
+                add_words(&mut map_normed, v, thresholds);
             }
         }
     }
 
+    Ok(map_normed)
+}
+
+// the reason why the word is not considered, if so
+fn skip_reason(w: &str, thresholds: &Thresholds) -> Option<&'static str> {
+    if thresholds.skip_uppercase && w.chars().all(|c| !c.is_lowercase()) {
+        Some("uppercase")
+    } else if thresholds.skip_digits && w.chars().any(|c| c.is_numeric()) {
+        Some("digits")
+    } else {
+        None
+    }
+}
+
+/// count the occurences of the words of the text, by normed form
+pub fn add_words(
+    map_normed: &mut BTreeMap<String, BTreeMap<String, u32>>,
+    text: &str,
+    thresholds: &Thresholds,
+) {
+    for w in text.split_whitespace() {
+        if skip_reason(w, thresholds).is_some() {
+            continue;
+        }
+        let map = map_normed.entry(utils::normed(w)).or_default();
+        *map.entry(w.to_string()).or_insert(0) += 1;
+    }
+}
+
+/// add the interesting words of the files to the dictionary, returning them,
+/// the decision about every normed form being written in the candidates file if given
+pub fn populate_dict_from_files(
+    files: &[CorpusFile],
+    ispell: &mut SpellCheck,
+    conf_path: &Path,
+    thresholds: &Thresholds,
+    candidates_file: Option<&Path>,
+) -> Result<Vec<String>> {
+    if files.is_empty() {
+        return Ok(vec![]);
+    }
+    // with a candidates file, all the words are read to write the skipped ones too
+    let (map_normed, skipped) = if candidates_file.is_some() {
+        let all_thresholds = Thresholds {
+            skip_uppercase: false,
+            skip_digits: false,
+            ..thresholds.clone()
+        };
+        split_skipped(read_words(files, conf_path, &all_thresholds)?, thresholds)
+    } else {
+        (read_words(files, conf_path, thresholds)?, BTreeMap::new())
+    };
     let corpus_size: u32 = map_normed.values().flat_map(|m| m.values()).sum();
-    println!("BANO corpus size = {}", corpus_size);
-    let mut nb_added = 0;
-    for map in map_normed.values() {
-        if let Some(interesting_word) = get_interesting_word(map) {
-            if (map[&interesting_word] >= corpus_size / 100_000
-                || !ispell.has_competitor_word(&interesting_word)?)
-                && !ispell.has_same_accent_word(&interesting_word)?
-            {
-                let _ = ispell.add_word(&interesting_word); // ignore the error
-                nb_added += 1;
+    info!("BANO corpus size = {}", corpus_size);
+    let min_count = corpus_size
+        .checked_div(thresholds.frequency_divisor)
+        .unwrap_or(0);
+    let mut added = vec![];
+    let mut candidates = vec![];
+    for (normed, map) in &map_normed {
+        let word = get_interesting_word(map, thresholds.dominance_ratio);
+        let (is_added, reason) = match word {
+            None => (false, "no dominant form"),
+            Some(ref w) => {
+                let frequent = map[w] >= min_count;
+                if !frequent && ispell.has_competitor_word(w)? {
+                    (false, "not frequent, with a competitor word in dictionary")
+                } else if ispell.has_same_accent_word(w)? {
+                    (false, "already in dictionary")
+                } else {
+                    let _ = ispell.add_word(w); // ignore the error
+                    added.push(w.clone());
+                    if frequent {
+                        (true, "frequent")
+                    } else {
+                        (true, "no competitor word in dictionary")
+                    }
+                }
+            }
+        };
+        if candidates_file.is_some() {
+            let decision = if is_added { "added" } else { "rejected" };
+            candidates.push(Candidate::new(normed, map, word, decision, reason));
+        }
+    }
+    info!("Added {} words to dictionnary", added.len());
+    if let Some(path) = candidates_file {
+        for (&(ref normed, reason), map) in &skipped {
+            candidates.push(Candidate::new(normed, map, None, "skipped", reason));
+        }
+        // stable sort, the skipped forms of a normed form following its decision
+        candidates.sort_by(|a, b| a.normed.cmp(&b.normed));
+        write_candidates(path, &candidates)
+            .chain_err(|| format!("Could not write candidates file {}", path.display()))?;
+    }
+    Ok(added)
+}
+
+type SkippedWords = BTreeMap<(String, &'static str), BTreeMap<String, u32>>;
+
+// the words considered according to the thresholds,
+// and the skipped ones by normed form and reason
+fn split_skipped(
+    map_normed: BTreeMap<String, BTreeMap<String, u32>>,
+    thresholds: &Thresholds,
+) -> (BTreeMap<String, BTreeMap<String, u32>>, SkippedWords) {
+    let mut kept_normed = BTreeMap::new();
+    let mut skipped = BTreeMap::new();
+    for (normed, map) in map_normed {
+        let mut kept = BTreeMap::new();
+        for (w, count) in map {
+            match skip_reason(&w, thresholds) {
+                Some(reason) => {
+                    skipped
+                        .entry((normed.clone(), reason))
+                        .or_insert_with(BTreeMap::new)
+                        .insert(w, count);
+                }
+                None => {
+                    kept.insert(w, count);
+                }
             }
         }
+        if !kept.is_empty() {
+            kept_normed.insert(normed, kept);
+        }
+    }
+    (kept_normed, skipped)
+}
+
+fn write_candidates(path: &Path, candidates: &[Candidate]) -> Result<()> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    for c in candidates {
+        wtr.serialize(c)?;
+    }
+    wtr.flush()?;
+    Ok(())
+}
+
+/// same as populate_dict_from_files, the added words being kept in the cache directory
+/// until the files, the thresholds or the dictionary change
+/// (the cache is not used if a candidates file is given, to write it),
+/// the cache file being read or written once for all the processors sharing the caches
+#[allow(clippy::too_many_arguments)]
+pub fn populate_dict_with_cache(
+    files: &[CorpusFile],
+    ispell: &mut SpellCheck,
+    conf_path: &Path,
+    thresholds: &Thresholds,
+    candidates_file: Option<&Path>,
+    cache_dir: &Path,
+    dictionary: &str,
+    caches: &SharedCaches,
+) -> Result<()> {
+    let mut words_hash = hash_corpus(files, conf_path)?;
+    words_hash.write(format!("{:?}", thresholds).as_bytes());
+    // the words already added (ex: from added_words) change the words kept
+    words_hash.write(ispell.added_words_hash().to_hex().as_bytes());
+    let key = CacheKey {
+        dictionary: dictionary.to_string(),
+        dictionary_hash: ispell.dictionary_hash()?.to_hex(),
+        words_hash: words_hash.to_hex(),
+    };
+    let path = spell_cache::cache_path(cache_dir, &key, "bano");
+    let mut populated = false;
+    let words = caches.added_words(&path, || {
+        let cached = match candidates_file {
+            Some(_) => None,
+            None => spell_cache::read_entries::<String>(&path, &key)?,
+        };
+        match cached {
+            Some(words) => Ok(words),
+            None => {
+                let words = populate_dict_from_files(
+                    files,
+                    ispell,
+                    conf_path,
+                    thresholds,
+                    candidates_file,
+                )?;
+                spell_cache::write_entries(&path, &key, &words)?;
+                populated = true;
+                Ok(words)
+            }
+        }
+    })?;
+    if !populated {
+        for w in &words {
+            let _ = ispell.add_word(w); // ignore the error
+        }
+        info!("Added {} words to dictionnary from cache {}", words.len(), path.display());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Source {
+    #[serde(flatten)]
+    corpus: CorpusFile,
+    hash: String,
+}
+
+// first line of an added words file
+#[derive(Debug, Serialize, Deserialize)]
+struct AddedWordsHeader {
+    dictionary: String,
+    dictionary_hash: String,
+    sources: Vec<Source>,
+    #[serde(default)]
+    thresholds: Thresholds,
+}
+
+// path of the file relative to the directory (both existing)
+fn relative_path(file: &Path, dir: &Path) -> Result<PathBuf> {
+    let file = fs::canonicalize(file).chain_err(|| format!("Could not find {}", file.display()))?;
+    let dir = fs::canonicalize(dir).chain_err(|| format!("Could not find {}", dir.display()))?;
+    let nb_common = file.components()
+        .zip(dir.components())
+        .take_while(|&(f, d)| f == d)
+        .count();
+    let mut path: PathBuf = dir.components().skip(nb_common).map(|_| "..").collect();
+    path.extend(file.components().skip(nb_common));
+    Ok(path)
+}
+
+// directory of a file, the current one if the path has no parent
+fn parent_dir(path: &Path) -> &Path {
+    match path.parent() {
+        Some(p) if p != Path::new("") => p,
+        _ => Path::new("."),
+    }
+}
+
+/// write the words added to the dictionary from the files,
+/// with the hashes of the dictionary and of the files
+/// (whose paths are relative to the output file)
+pub fn build_dict(
+    files: &[CorpusFile],
+    ispell: &mut SpellCheck,
+    dictionary: &str,
+    output: &Path,
+    thresholds: &Thresholds,
+    candidates_file: Option<&Path>,
+) -> Result<()> {
+    let mut sources = vec![];
+    for f in files {
+        let file = relative_path(Path::new(&f.file), parent_dir(output))?;
+        sources.push(Source {
+            corpus: CorpusFile {
+                file: file.to_string_lossy().into_owned(),
+                ..f.clone()
+            },
+            hash: spell_cache::hash_files(&[&f.file])?.to_hex(),
+        });
+    }
+    let header = AddedWordsHeader {
+        dictionary: dictionary.to_string(),
+        dictionary_hash: ispell.dictionary_hash()?.to_hex(),
+        sources,
+        thresholds: thresholds.clone(),
+    };
+    let words =
+        populate_dict_from_files(files, ispell, &PathBuf::new(), thresholds, candidates_file)?;
+    spell_cache::write_entries(output, &header, &words)
+        .chain_err(|| format!("Could not write {}", output.display()))
+}
+
+/// add the words of a file written by build_dict to the dictionary,
+/// warning if the dictionary or the files changed since
+pub fn load_added_words(ispell: &mut SpellCheck, path: &Path) -> Result<()> {
+    let (header, words): (AddedWordsHeader, Vec<String>) = spell_cache::read_all(path)
+        .chain_err(|| format!("Could not read added words file {}", path.display()))?;
+    if ispell.dictionary_hash()?.to_hex() != header.dictionary_hash {
+        warn!(
+            "Dictionary {} changed since {} was built",
+            header.dictionary,
+            path.display()
+        );
+    }
+    for source in &header.sources {
+        let file = parent_dir(path).join(&source.corpus.file);
+        match spell_cache::hash_files(&[&file]) {
+            Ok(ref hash) if hash.to_hex() == source.hash => {}
+            Ok(_) => warn!("{} changed since {} was built", file.display(), path.display()),
+            Err(_) => warn!("{} not found, {} may be outdated", file.display(), path.display()),
+        }
     }
-    println!("Added {} words to dictionnary", nb_added);
+    for w in &words {
+        let _ = ispell.add_word(w); // ignore the error
+    }
+    info!("Added {} words to dictionnary from {}", words.len(), path.display());
     Ok(())
 }
 
-fn get_interesting_word(map: &BTreeMap<String, u32>) -> Option<String> {
+/// the form appearing dominance_ratio times more than any other one, if any
+pub fn get_interesting_word(map: &BTreeMap<String, u32>, dominance_ratio: u32) -> Option<String> {
     let mut map_iter = map.iter();
     let mut first_max_w = map_iter.next().expect("This map should never be empty");
     let mut second_max_count = 0;
@@ -79,51 +489,57 @@ fn get_interesting_word(map: &BTreeMap<String, u32>) -> Option<String> {
 
     // first max contains the forms appearing more and its occurences
     // second max reports the occurences of the second form appearing more
-    // if the first form appears 4 times (empirical) more than the second one, it is qualified
-    if second_max_count == 0 || (*first_max_w.1 / second_max_count) >= 4 {
+    // if the first form appears dominance_ratio times more than the second one, it is qualified
+    if second_max_count == 0 || (*first_max_w.1 / second_max_count) >= dominance_ratio {
         return Some(first_max_w.0.clone());
     }
     None
 }
 
-struct Bano {
-    pub street: String,
-    pub city: String,
-}
-
-struct BanoIter<'a, R: io::Read + 'a> {
+// values of the columns containing words
+struct CorpusIter<'a, R: io::Read + 'a> {
     iter: csv::StringRecordsIter<'a, R>,
-    street_pos: usize,
-    city_pos: usize,
+    positions: Vec<usize>,
 }
-impl<'a, R: io::Read + 'a> BanoIter<'a, R> {
-    fn new(r: &'a mut csv::Reader<R>) -> Self {
-        BanoIter {
-            iter: r.records(),
-            street_pos: 2,
-            city_pos: 4,
+impl<'a, R: io::Read + 'a> CorpusIter<'a, R> {
+    fn new(r: &'a mut csv::Reader<R>, columns: &[Column], has_headers: bool) -> Result<Self> {
+        let mut positions = vec![];
+        for c in columns {
+            let pos = match *c {
+                Column::Index(i) => i,
+                Column::Name(ref name) if !has_headers => bail!(
+                    "Column {} is given by its header, but the file has no header line \
+                     (see has_headers, or --headers of build-dict)",
+                    name
+                ),
+                Column::Name(ref name) => records_reader::column_position(r.headers()?, name)?,
+            };
+            positions.push(pos);
         }
+        Ok(CorpusIter {
+            iter: r.records(),
+            positions,
+        })
     }
 
-    fn make_bano(&self, item: csv::Result<csv::StringRecord>) -> Result<Bano> {
-        fn get(record: &[String], pos: usize) -> Result<&str> {
-            match record.get(pos) {
-                Some(s) => Ok(s),
-                None => Err(ErrorKind::ColumnNotFound(pos.to_string()).into()),
-            }
-        }
-
+    fn make_values(&self, item: csv::Result<csv::StringRecord>) -> Result<Vec<String>> {
         let record = item?;
-        let r: Vec<String> = record.deserialize(None)?;
-        let street = get(&r, self.street_pos)?.to_string();
-        let city = get(&r, self.city_pos)?.to_string();
-        Ok(Bano { street, city })
+        self.positions
+            .iter()
+            .map(|&pos| match record.get(pos) {
+                Some(s) => Ok(s.to_string()),
+                None => {
+                    let available = (0..record.len()).map(|i| i.to_string()).collect();
+                    Err(ErrorKind::ColumnNotFound(pos.to_string(), available).into())
+                }
+            })
+            .collect()
     }
 }
 
-impl<'a, R: io::Read + 'a> Iterator for BanoIter<'a, R> {
-    type Item = Result<Bano>;
+impl<'a, R: io::Read + 'a> Iterator for CorpusIter<'a, R> {
+    type Item = Result<Vec<String>>;
     fn next(&mut self) -> Option<Self::Item> {
-        self.iter.next().map(|item| self.make_bano(item))
+        self.iter.next().map(|item| self.make_values(item))
     }
 }
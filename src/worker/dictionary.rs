@@ -0,0 +1,493 @@
+use encoding::label::encoding_from_whatwg_label;
+use encoding::DecoderTrap;
+use std::collections::{BTreeSet, HashMap};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use utils;
+use super::spell_backend::{Misspelling, SpellBackend};
+use super::spell_cache::{self, Hash};
+use errors::{Result, ResultExt};
+
+/// in-process dictionary, loaded from Hunspell .aff/.dic files or from a word list
+#[derive(Default)]
+pub struct Dictionary {
+    // normed form -> forms of the dictionary
+    forms: HashMap<String, BTreeSet<String>>,
+    hash: Hash,
+}
+impl Dictionary {
+    /// one word per line (empty lines and lines starting with # are ignored)
+    pub fn from_word_list(file_path: &Path) -> Result<Self> {
+        let content = read_file(file_path, None)?;
+        let mut dict = Dictionary {
+            hash: spell_cache::hash_files(&[file_path])?,
+            ..Dictionary::default()
+        };
+        for line in content.lines().map(str::trim) {
+            if !line.is_empty() && !line.starts_with('#') {
+                dict.insert(line);
+            }
+        }
+        Ok(dict)
+    }
+
+    /// all the forms of the words of the .dic file, using the affixes of the .aff file
+    /// (the path is the one of the .dic file, with or without its extension)
+    pub fn from_hunspell(dict_path: &Path) -> Result<Self> {
+        let base = dict_path.to_string_lossy();
+        let base = base.trim_end_matches(".dic");
+        let aff_path = format!("{}.aff", base);
+        let dic_path = format!("{}.dic", base);
+
+        let encoding = read_encoding(Path::new(&aff_path))?;
+        let encoding = encoding.as_deref();
+        let affixes = Affixes::parse(&read_file(Path::new(&aff_path), encoding)?);
+        let dic_content = read_file(Path::new(&dic_path), encoding)?;
+
+        let mut dict = Dictionary {
+            hash: spell_cache::hash_files(&[&aff_path, &dic_path])?,
+            ..Dictionary::default()
+        };
+        dict.insert_dic(&affixes, &dic_content);
+        Ok(dict)
+    }
+
+    // the forms of the words of the content of a .dic file
+    fn insert_dic(&mut self, affixes: &Affixes, dic_content: &str) {
+        let mut forms = vec![];
+        // the first line is the number of words
+        for line in dic_content.lines().skip(1) {
+            // morphological fields follow the word, separated by a tab or spaces
+            let entry = line.split(['\t', ' ']).next().unwrap_or("");
+            if entry.is_empty() {
+                continue;
+            }
+            let (word, flags) = match entry.find('/') {
+                Some(i) => (&entry[..i], affixes.parse_flags(&entry[i + 1..])),
+                None => (entry, vec![]),
+            };
+            affixes.expand(word, &flags, &mut forms);
+            for f in forms.drain(..) {
+                self.insert(&f);
+            }
+        }
+    }
+
+    /// all the forms of the dictionary
+    pub fn forms<'a>(&'a self) -> impl Iterator<Item = &'a String> + 'a {
+        self.forms.values().flat_map(|f| f.iter())
+    }
+
+    fn insert(&mut self, word: &str) {
+        self.forms
+            .entry(utils::normed(word))
+            .or_default()
+            .insert(word.to_string());
+    }
+
+    // a word is known as is, capitalized or in uppercase
+    fn is_known(&self, word: &str) -> bool {
+        match self.forms.get(&utils::normed(word)) {
+            Some(forms) => forms
+                .iter()
+                .any(|f| f == word || utils::first_upper(f) == word || f.to_uppercase() == word),
+            None => false,
+        }
+    }
+
+    // the forms with the same normed form, with the case of the word
+    fn suggestions(&self, word: &str) -> Vec<String> {
+        let forms = match self.forms.get(&utils::normed(word)) {
+            Some(forms) => forms,
+            None => return vec![],
+        };
+        let suggestions: BTreeSet<String> =
+            forms.iter().map(|f| utils::with_case_of(f, word)).collect();
+        suggestions.into_iter().collect()
+    }
+}
+impl SpellBackend for Dictionary {
+    fn check(&mut self, text: &str) -> Result<Vec<Misspelling>> {
+        let mut misspellings = vec![];
+        let mut position = 0;
+        for w in utils::get_words(text) {
+            let start = position;
+            position += w.len();
+            // only words made of letters are checked
+            if !w.chars().next().is_some_and(char::is_alphabetic)
+                || w.chars().any(char::is_numeric) || self.is_known(w)
+            {
+                continue;
+            }
+            misspellings.push(Misspelling {
+                misspelled: w.to_string(),
+                position: start,
+                suggestions: self.suggestions(w),
+            });
+        }
+        Ok(misspellings)
+    }
+
+    fn add_word(&mut self, word: &str) -> Result<()> {
+        self.insert(word);
+        Ok(())
+    }
+
+    fn dictionary_hash(&self) -> Result<Hash> {
+        Ok(self.hash)
+    }
+}
+
+fn read_bytes(file_path: &Path) -> Result<Vec<u8>> {
+    let mut bytes = vec![];
+    File::open(file_path)
+        .and_then(|mut f| f.read_to_end(&mut bytes))
+        .chain_err(|| format!("Could not read dictionary file {}", file_path.display()))?;
+    Ok(bytes)
+}
+
+// decoded with the given encoding, or as utf-8
+fn read_file(file_path: &Path, encoding: Option<&str>) -> Result<String> {
+    let bytes = read_bytes(file_path)?;
+    let enc = match encoding {
+        Some(e) => encoding_from_whatwg_label(e)
+            .ok_or_else(|| format!("Could not find encoding from {}", e))?,
+        None => {
+            return String::from_utf8(bytes)
+                .chain_err(|| format!("{} is not valid utf-8", file_path.display()))
+        }
+    };
+    enc.decode(&bytes, DecoderTrap::Replace)
+        .map_err(|e| format!("Could not decode {}: {}", file_path.display(), e).into())
+}
+
+// the encoding of the dictionary files, given by SET in the .aff file
+fn read_encoding(aff_path: &Path) -> Result<Option<String>> {
+    let bytes = read_bytes(aff_path)?;
+    let encoding = String::from_utf8_lossy(&bytes)
+        .lines()
+        .filter_map(|l| {
+            let mut fields = l.split_whitespace();
+            match fields.next() {
+                Some("SET") => fields.next().map(|e| e.to_string()),
+                _ => None,
+            }
+        })
+        .next();
+    Ok(encoding)
+}
+
+#[derive(Clone, Copy)]
+enum FlagMode {
+    Char,
+    Long,
+    Num,
+}
+
+enum CondChar {
+    Any,
+    In(Vec<char>),
+    NotIn(Vec<char>),
+}
+impl CondChar {
+    fn matches(&self, c: char) -> bool {
+        match *self {
+            CondChar::Any => true,
+            CondChar::In(ref set) => set.contains(&c),
+            CondChar::NotIn(ref set) => !set.contains(&c),
+        }
+    }
+}
+
+// condition on the characters at the end (suffix) or start (prefix) of a word,
+// ex: [^aeiou]y
+fn parse_condition(condition: &str) -> Vec<CondChar> {
+    let mut conds = vec![];
+    let mut chars = condition.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '.' => conds.push(CondChar::Any),
+            '[' => {
+                let set: Vec<char> = chars.by_ref().take_while(|c| *c != ']').collect();
+                if set.first() == Some(&'^') {
+                    conds.push(CondChar::NotIn(set[1..].to_vec()));
+                } else {
+                    conds.push(CondChar::In(set));
+                }
+            }
+            c => conds.push(CondChar::In(vec![c])),
+        }
+    }
+    conds
+}
+
+struct AffixRule {
+    strip: String,
+    add: String,
+    // continuation flags (twofold affixes)
+    flags: Vec<String>,
+    condition: Vec<CondChar>,
+}
+impl AffixRule {
+    fn apply(&self, word: &str, is_suffix: bool) -> Option<String> {
+        let chars: Vec<char> = word.chars().collect();
+        let n = self.condition.len();
+        if chars.len() < n || word.len() <= self.strip.len() {
+            return None;
+        }
+        if is_suffix {
+            let end = &chars[chars.len() - n..];
+            if !word.ends_with(&self.strip)
+                || !self.condition.iter().zip(end).all(|(c, ch)| c.matches(*ch))
+            {
+                return None;
+            }
+            Some(format!("{}{}", &word[..word.len() - self.strip.len()], self.add))
+        } else {
+            if !word.starts_with(&self.strip)
+                || !self.condition.iter().zip(&chars).all(|(c, ch)| c.matches(*ch))
+            {
+                return None;
+            }
+            Some(format!("{}{}", self.add, &word[self.strip.len()..]))
+        }
+    }
+}
+
+struct AffixClass {
+    is_suffix: bool,
+    cross_product: bool,
+    rules: Vec<AffixRule>,
+}
+
+// the part of the .aff file needed to generate the forms of the words
+struct Affixes {
+    flag_mode: FlagMode,
+    // flag sets given by number (AF)
+    aliases: Vec<String>,
+    classes: HashMap<String, AffixClass>,
+    // the word itself is not valid, only its affixed forms
+    need_affix: Option<String>,
+}
+impl Affixes {
+    fn parse(content: &str) -> Self {
+        let mut affixes = Affixes {
+            flag_mode: FlagMode::Char,
+            aliases: vec![],
+            classes: HashMap::new(),
+            need_affix: None,
+        };
+        let mut af_header_read = false;
+        for line in content.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            match (fields.first(), fields.get(1)) {
+                (Some(&"FLAG"), Some(&"long")) => affixes.flag_mode = FlagMode::Long,
+                (Some(&"FLAG"), Some(&"num")) => affixes.flag_mode = FlagMode::Num,
+                (Some(&"NEEDAFFIX"), Some(f)) => affixes.need_affix = Some(f.to_string()),
+                (Some(&"AF"), Some(f)) => {
+                    // the first AF line is the number of aliases
+                    if af_header_read {
+                        affixes.aliases.push(f.to_string());
+                    }
+                    af_header_read = true;
+                }
+                (Some(&kind), Some(&flag)) if kind == "PFX" || kind == "SFX" => {
+                    affixes.parse_affix_line(kind == "SFX", flag, &fields)
+                }
+                _ => {}
+            }
+        }
+        affixes
+    }
+
+    // SFX flag cross_product count (first line of a class)
+    // SFX flag strip add[/flags] [condition]
+    fn parse_affix_line(&mut self, is_suffix: bool, flag: &str, fields: &[&str]) {
+        if !self.classes.contains_key(flag) {
+            self.classes.insert(
+                flag.to_string(),
+                AffixClass {
+                    is_suffix,
+                    cross_product: fields.get(2) == Some(&"Y"),
+                    rules: vec![],
+                },
+            );
+            return;
+        }
+        if fields.len() < 4 {
+            return;
+        }
+        let strip = if fields[2] == "0" { "" } else { fields[2] };
+        let (add, flags) = match fields[3].find('/') {
+            Some(i) => (&fields[3][..i], self.parse_flags(&fields[3][i + 1..])),
+            None => (fields[3], vec![]),
+        };
+        let add = if add == "0" { "" } else { add };
+        let rule = AffixRule {
+            strip: strip.to_string(),
+            add: add.to_string(),
+            flags,
+            condition: parse_condition(fields.get(4).cloned().unwrap_or(".")),
+        };
+        if let Some(class) = self.classes.get_mut(flag) {
+            class.rules.push(rule);
+        }
+    }
+
+    fn parse_flags(&self, flags: &str) -> Vec<String> {
+        let flags = if self.aliases.is_empty() {
+            flags
+        } else {
+            match flags.parse::<usize>() {
+                Ok(i) if i >= 1 && i <= self.aliases.len() => &self.aliases[i - 1],
+                _ => flags,
+            }
+        };
+        match self.flag_mode {
+            FlagMode::Char => flags.chars().map(|c| c.to_string()).collect(),
+            FlagMode::Long => flags
+                .chars()
+                .collect::<Vec<_>>()
+                .chunks(2)
+                .map(|c| c.iter().collect())
+                .collect(),
+            FlagMode::Num => flags
+                .split(',')
+                .map(str::trim)
+                .filter(|f| !f.is_empty())
+                .map(|f| f.to_string())
+                .collect(),
+        }
+    }
+
+    fn needs_affix(&self, flags: &[String]) -> bool {
+        self.need_affix
+            .as_ref()
+            .is_some_and(|n| flags.iter().any(|f| f == n))
+    }
+
+    fn classes<'a>(&'a self, flags: &'a [String], is_suffix: bool) -> Vec<&'a AffixClass> {
+        flags
+            .iter()
+            .filter_map(|f| self.classes.get(f))
+            .filter(|c| c.is_suffix == is_suffix)
+            .collect()
+    }
+
+    // the word and its forms with the affixes of its flags
+    // (suffixes followed by their continuation suffixes, and combined with prefixes)
+    fn expand(&self, word: &str, flags: &[String], forms: &mut Vec<String>) {
+        if !self.needs_affix(flags) {
+            forms.push(word.to_string());
+        }
+        let prefixes = self.classes(flags, false);
+        for class in self.classes(flags, true) {
+            for rule in &class.rules {
+                let form = match rule.apply(word, true) {
+                    Some(f) => f,
+                    None => continue,
+                };
+                for cont in self.classes(&rule.flags, true) {
+                    forms.extend(cont.rules.iter().filter_map(|r| r.apply(&form, true)));
+                }
+                if class.cross_product {
+                    for p in prefixes.iter().filter(|p| p.cross_product) {
+                        forms.extend(p.rules.iter().filter_map(|r| r.apply(&form, false)));
+                    }
+                }
+                if !self.needs_affix(&rule.flags) {
+                    forms.push(form);
+                }
+            }
+        }
+        for p in prefixes {
+            forms.extend(p.rules.iter().filter_map(|r| r.apply(word, false)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use super::{Affixes, Dictionary};
+
+    // the forms of the words of the .dic content, with the affixes of the .aff content
+    fn forms(aff: &str, dic: &str) -> BTreeSet<String> {
+        let mut dict = Dictionary::default();
+        dict.insert_dic(&Affixes::parse(aff), dic);
+        dict.forms().cloned().collect()
+    }
+
+    fn set(words: &[&str]) -> BTreeSet<String> {
+        words.iter().map(|w| w.to_string()).collect()
+    }
+
+    #[test]
+    fn char_flags() {
+        let aff = "SFX S Y 1\nSFX S 0 s .\n";
+        assert_eq!(forms(aff, "2\nchat/S\nchien\n"), set(&["chat", "chats", "chien"]));
+    }
+
+    #[test]
+    fn morphological_fields() {
+        let aff = "SFX S Y 1\nSFX S 0 s .\n";
+        assert_eq!(forms(aff, "1\nchat/S po:nom\n"), set(&["chat", "chats"]));
+    }
+
+    #[test]
+    fn conditions() {
+        let aff = "SFX X Y 2\nSFX X y ies [^aeiou]y\nSFX X 0 s [aeiou]y\n";
+        assert_eq!(forms(aff, "2\nfly/X\nday/X\n"), set(&["day", "days", "flies", "fly"]));
+    }
+
+    #[test]
+    fn long_flags_and_cross_product() {
+        let aff = "FLAG long\nSFX Aa Y 1\nSFX Aa 0 s .\nPFX Bb Y 1\nPFX Bb 0 re .\n";
+        assert_eq!(
+            forms(aff, "1\nlire/AaBb\n"),
+            set(&["lire", "lires", "relire", "relires"])
+        );
+    }
+
+    #[test]
+    fn no_cross_product() {
+        let aff = "SFX S Y 1\nSFX S 0 s .\nPFX R N 1\nPFX R 0 re .\n";
+        assert_eq!(forms(aff, "1\nfaire/SR\n"), set(&["faire", "faires", "refaire"]));
+    }
+
+    #[test]
+    fn num_flags() {
+        let aff = "FLAG num\nSFX 101 Y 1\nSFX 101 0 s .\nPFX 7 Y 1\nPFX 7 0 re .\n";
+        assert_eq!(
+            forms(aff, "1\nfaire/101,7\n"),
+            set(&["faire", "faires", "refaire", "refaires"])
+        );
+    }
+
+    #[test]
+    fn aliases() {
+        let aff = "AF 2\nAF S\nAF SR\nSFX S Y 1\nSFX S 0 s .\nPFX R Y 1\nPFX R 0 re .\n";
+        assert_eq!(
+            forms(aff, "2\nmot/1\nport/2\n"),
+            set(&["mot", "mots", "port", "ports", "report", "reports"])
+        );
+    }
+
+    #[test]
+    fn need_affix() {
+        let aff = "NEEDAFFIX !\nSFX E Y 1\nSFX E 0 e .\n";
+        assert_eq!(forms(aff, "1\nchos/!E\n"), set(&["chose"]));
+    }
+
+    #[test]
+    fn continuation_classes() {
+        let aff = "SFX A Y 1\nSFX A 0 eur/B .\nSFX B Y 1\nSFX B 0 s .\n";
+        assert_eq!(
+            forms(aff, "1\nchant/A\n"),
+            set(&["chant", "chanteur", "chanteurs"])
+        );
+        let aff = "NEEDAFFIX !\nSFX A Y 1\nSFX A 0 eur/B! .\nSFX B Y 1\nSFX B 0 s .\n";
+        assert_eq!(forms(aff, "1\nchant/A\n"), set(&["chant", "chanteurs"]));
+    }
+}
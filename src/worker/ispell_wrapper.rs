@@ -1,49 +1,159 @@
-use ispell;
+use csv;
+use super::spell_backend::{Misspelling, SpellBackend};
+use super::spell_cache::{self, CacheKey, Hash, SharedCaches, WordsCache};
 use errors::{Result, ResultExt};
 use utils;
-use std::collections::BTreeSet;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
 
 struct SpellCache {
     name: String,
-    errors: Vec<ispell::IspellError>,
+    errors: Vec<Misspelling>,
 }
 impl SpellCache {
-    fn new(checker: &mut ispell::SpellChecker, name: &str) -> Result<Self> {
+    fn new(checker: &mut dyn SpellBackend, name: &str) -> Result<Self> {
         Ok(SpellCache {
             name: name.to_string(),
-            errors: checker
-                .check(name)
-                .chain_err(|| "Could not perform check using aspell")?,
+            errors: checker.check(name)?,
         })
     }
+
+    // errors of the name, only checked if it's not the last name checked
+    fn get<'a>(
+        cache: &'a mut Option<SpellCache>,
+        checker: &mut dyn SpellBackend,
+        name: &str,
+    ) -> Result<&'a [Misspelling]> {
+        if cache.as_ref().is_none_or(|cache| cache.name != name) {
+            *cache = Some(SpellCache::new(checker, name)?);
+        }
+        Ok(&cache.as_ref().unwrap().errors)
+    }
+}
+
+/// several valid accented suggestions for a misspelled word
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ambiguity {
+    pub misspelled: String,
+    pub suggestions: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct WordChoice {
+    misspelled: String,
+    #[serde(default)]
+    choice: String,
+}
+
+/// read the choices made for misspelled words (in an ambiguities file),
+/// rows without choice are ignored, different choices for a word are an error
+pub fn read_word_choices(file_path: &Path) -> Result<HashMap<String, String>> {
+    let mut rdr = csv::Reader::from_path(file_path)
+        .chain_err(|| format!("Could not open word choices file {}", file_path.display()))?;
+    let mut choices = HashMap::new();
+    for res_row in rdr.deserialize() {
+        let row: WordChoice = res_row
+            .chain_err(|| format!("error at csv line decoding: {}", file_path.display()))?;
+        if row.choice.is_empty() {
+            continue;
+        }
+        if let Some(choice) = choices.get(&row.misspelled) {
+            if *choice != row.choice {
+                bail!(
+                    "Conflicting choices for {} in {}: {} and {}",
+                    row.misspelled,
+                    file_path.display(),
+                    choice,
+                    row.choice
+                );
+            }
+        }
+        choices.insert(row.misspelled, row.choice);
+    }
+    Ok(choices)
 }
 
 pub struct SpellCheck {
-    aspell: ispell::SpellChecker,
+    backend: Box<dyn SpellBackend>,
     cache: Option<SpellCache>,
+    // results per word of the names
+    words_cache: WordsCache,
+    dictionary_hash: Option<Hash>,
+    added_words_hash: Hash,
+    ambiguities: Vec<Ambiguity>,
+    word_choices: HashMap<String, String>,
 }
 impl SpellCheck {
-    pub fn new(dict: &str) -> Result<Self> {
-        Ok(SpellCheck {
-            aspell: ispell::SpellLauncher::new()
-                .aspell()
-                .dictionary(dict)
-                .timeout(10_000)
-                .launch()?,
+    pub fn new(backend: Box<dyn SpellBackend>) -> Self {
+        SpellCheck {
+            backend,
             cache: None,
-        })
+            words_cache: WordsCache::new(),
+            dictionary_hash: None,
+            added_words_hash: Hash::default(),
+            ambiguities: vec![],
+            word_choices: HashMap::new(),
+        }
     }
 
-    pub fn add_word(&mut self, new_word: &str) -> Result<()> {
-        self.aspell.add_word(new_word)?;
+    /// hash of the dictionary files, computed once (aspell is run to find them)
+    pub fn dictionary_hash(&mut self) -> Result<Hash> {
+        if let Some(hash) = self.dictionary_hash {
+            return Ok(hash);
+        }
+        let hash = self.backend.dictionary_hash()?;
+        self.dictionary_hash = Some(hash);
+        Ok(hash)
+    }
+
+    /// use the hash of the dictionary computed by the first processor sharing the caches
+    pub fn share_dictionary_hash(&mut self, caches: &SharedCaches, dictionary: &str) -> Result<()> {
+        let backend = &self.backend;
+        let hash = caches.dictionary_hash(dictionary, || backend.dictionary_hash())?;
+        self.dictionary_hash = Some(hash);
         Ok(())
     }
 
-    fn get_ispell_errors(&mut self, word: &str) -> Result<&[ispell::IspellError]> {
-        if self.cache.as_ref().map_or(true, |cache| cache.name != word) {
-            self.cache = Some(SpellCache::new(&mut self.aspell, word)?);
-        }
-        Ok(&self.cache.as_ref().unwrap().errors)
+    /// hash of the words added to the dictionary so far
+    pub fn added_words_hash(&self) -> Hash {
+        self.added_words_hash
+    }
+
+    /// keep the results of the spell-checker in the cache directory for next runs
+    /// (to be called once all words are added to the dictionary),
+    /// the cache file being shared with the other processors sharing the caches
+    pub fn open_cache(
+        &mut self,
+        cache_dir: &Path,
+        dictionary: &str,
+        caches: &SharedCaches,
+    ) -> Result<()> {
+        let key = CacheKey {
+            dictionary: dictionary.to_string(),
+            dictionary_hash: self.dictionary_hash()?.to_hex(),
+            words_hash: self.added_words_hash.to_hex(),
+        };
+        let path = spell_cache::cache_path(cache_dir, &key, "checked");
+        self.words_cache = caches.words_cache(&path, &key)?;
+        Ok(())
+    }
+
+    /// words chosen by hand for misspelled words,
+    /// used instead of aspell suggestions
+    pub fn set_word_choices(&mut self, word_choices: HashMap<String, String>) {
+        self.word_choices = word_choices;
+    }
+
+    pub fn add_word(&mut self, new_word: &str) -> Result<()> {
+        self.added_words_hash.write(new_word.as_bytes());
+        self.added_words_hash.write(b"\n");
+        // an added word may change the results of any word
+        self.words_cache = WordsCache::new();
+        self.backend.add_word(new_word)
+    }
+
+    fn get_ispell_errors(&mut self, word: &str) -> Result<&[Misspelling]> {
+        SpellCache::get(&mut self.cache, &mut *self.backend, word)
     }
 
     // check for the presence of the same word, no matter the case
@@ -83,15 +193,23 @@ impl SpellCheck {
         Ok(false)
     }
 
-    pub fn process(&mut self, name: &str) -> Result<String> {
-        let misspelt_errors = self.get_ispell_errors(name)?;
+    /// ambiguities met since last call
+    pub fn take_ambiguities(&mut self) -> Vec<Ambiguity> {
+        ::std::mem::take(&mut self.ambiguities)
+    }
 
+    pub fn process(&mut self, name: &str) -> Result<String> {
         let mut new_name = name.to_string();
+        let misspelt_errors = self.words_cache.check(&mut *self.backend, name)?;
 
         for e in misspelt_errors
             .iter()
             .filter(|e| !utils::has_accent(&e.misspelled))
         {
+            if let Some(choice) = self.word_choices.get(&e.misspelled) {
+                new_name = new_name.replace(&e.misspelled, choice);
+                continue;
+            }
             let normed_miss = utils::normed(&e.misspelled);
             // set_lowercase just helps ignoring concurrence between
             // suggestions differing just by case
@@ -105,12 +223,29 @@ impl SpellCheck {
             if valid_suggestions.len() == 1 && utils::has_accent(valid_suggestions[0]) {
                 new_name = new_name.replace(&e.misspelled, valid_suggestions[0]);
             } else if valid_suggestions.len() > 1 {
-                println!(
-                    "Aspell ambiguous suggestions for {} : {:?}",
+                info!(
+                    "Ambiguous suggestions for {} : {:?}",
                     e.misspelled, valid_suggestions
                 );
+                self.ambiguities.push(Ambiguity {
+                    misspelled: e.misspelled.clone(),
+                    suggestions: valid_suggestions.iter().map(|s| s.to_string()).collect(),
+                });
             }
         }
         Ok(new_name)
     }
 }
+impl Drop for SpellCheck {
+    fn drop(&mut self) {
+        let (nb_hits, nb_misses) = self.words_cache.stats();
+        if nb_hits + nb_misses > 0 {
+            info!(
+                "Spell-check: {} words checked, {} found in cache ({:.1}% hit rate)",
+                nb_hits + nb_misses,
+                nb_hits,
+                100. * nb_hits as f64 / (nb_hits + nb_misses) as f64
+            );
+        }
+    }
+}
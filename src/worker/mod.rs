@@ -1,19 +1,33 @@
+pub mod accent_restore;
 pub mod bano_reader;
+pub mod dictionary;
 pub mod ispell_wrapper;
+pub mod overrides;
 pub mod regex_processor;
+pub mod spell_backend;
+pub mod spell_cache;
 
 use conf;
 use utils;
 use errors::Result;
 
+/// a processor of the sequence, with its type and label from config
+pub struct Step {
+    pub kind: &'static str,
+    pub label: Option<String>,
+    pub processor: Processor,
+}
+
 pub enum Processor {
     Fixedcase(regex_processor::FixedcaseProcessor),
     RegexReplace(regex_processor::RegexReplace),
     Ispell(ispell_wrapper::SpellCheck),
+    AccentRestore(accent_restore::AccentRestore),
     Decode(conf::Decode),
     SnakeCase,
     FirstLetterUppercase,
     LogSuspicious(regex_processor::LogSuspicious),
+    Overrides(overrides::Overrides),
 }
 impl Processor {
     pub fn apply(&mut self, name: &str) -> Result<String> {
@@ -21,13 +35,54 @@ impl Processor {
             Processor::Fixedcase(ref p) => Ok(p.process(name)),
             Processor::RegexReplace(ref p) => Ok(p.process(name)),
             Processor::Ispell(ref mut p) => p.process(name),
+            Processor::AccentRestore(ref mut p) => Ok(p.process(name)),
             Processor::Decode(ref d) => utils::decode(name, &d.from_encoding),
             Processor::SnakeCase => Ok(utils::snake_case(name)),
             Processor::FirstLetterUppercase => Ok(utils::first_upper(name)),
-            Processor::LogSuspicious(ref l) => {
+            Processor::LogSuspicious(ref mut l) => {
                 l.process(name);
                 Ok(name.to_string())
             }
+            // overrides need the record, see forced_name
+            Processor::Overrides(_) => Ok(name.to_string()),
+        }
+    }
+
+    /// ambiguities met by the processor since last call
+    pub fn take_ambiguities(&mut self) -> Vec<ispell_wrapper::Ambiguity> {
+        match *self {
+            Processor::Ispell(ref mut p) => p.take_ambiguities(),
+            Processor::AccentRestore(ref mut p) => p.take_ambiguities(),
+            _ => vec![],
+        }
+    }
+
+    /// suspicious matches found by the processor since last call
+    pub fn take_suspicions(&mut self) -> Vec<regex_processor::Suspicion> {
+        match *self {
+            Processor::LogSuspicious(ref mut l) => l.take_suspicions(),
+            _ => vec![],
+        }
+    }
+
+    /// names of the input (column, name), read before processing,
+    /// to be used as corpus by the processors configured so
+    pub fn add_input_corpus(&mut self, names: &[(String, String)]) {
+        if let Processor::AccentRestore(ref mut p) = *self {
+            p.add_input_corpus(names);
+        }
+    }
+
+    /// name forced for the record by an Overrides processor
+    pub fn forced_name(
+        &self,
+        id: Option<&str>,
+        column: Option<&str>,
+        name: &str,
+    ) -> Option<String> {
+        match *self {
+            Processor::Overrides(ref o) => o.get(id, column, name).map(|s| s.to_string()),
+            _ => None,
         }
     }
 }
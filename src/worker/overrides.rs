@@ -0,0 +1,95 @@
+use csv;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+use errors::{Result, ResultExt};
+
+/// a row of an overrides file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OverrideRow {
+    #[serde(default)]
+    pub id: String,
+    #[serde(default)]
+    pub column: String,
+    #[serde(default)]
+    pub old_name: String,
+    pub new_name: String,
+}
+
+pub fn read_rows(file_path: &Path) -> Result<Vec<OverrideRow>> {
+    let mut rdr = csv::Reader::from_path(file_path)
+        .chain_err(|| format!("Could not open overrides file {}", file_path.display()))?;
+    let mut rows = vec![];
+    for res_row in rdr.deserialize() {
+        let row: OverrideRow = res_row
+            .chain_err(|| format!("error at csv line decoding: {}", file_path.display()))?;
+        rows.push(row);
+    }
+    Ok(rows)
+}
+
+/// write the overrides file at once: written to a temporary file next to it,
+/// then renamed, so that the previous rows are kept if the writing is interrupted
+pub fn write_rows(file_path: &Path, rows: &[OverrideRow]) -> Result<()> {
+    let mut tmp_path = file_path.as_os_str().to_owned();
+    tmp_path.push(".tmp");
+    let tmp_path = PathBuf::from(tmp_path);
+    {
+        let mut wtr = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_path(&tmp_path)
+            .chain_err(|| format!("Could not create overrides file {}", tmp_path.display()))?;
+        // the header is written even without rows, for rows to be appended
+        wtr.write_record(["id", "column", "old_name", "new_name"])?;
+        for row in rows {
+            wtr.serialize(row)?;
+        }
+        wtr.flush()?;
+    }
+    fs::rename(&tmp_path, file_path)
+        .chain_err(|| format!("Could not write overrides file {}", file_path.display()))
+}
+
+/// writer of rows at the end of an overrides file
+/// (written by write_rows beforehand, so that it has the expected header)
+pub fn append_rows(file_path: &Path) -> Result<csv::Writer<File>> {
+    let file = OpenOptions::new()
+        .append(true)
+        .open(file_path)
+        .chain_err(|| format!("Could not open overrides file {}", file_path.display()))?;
+    Ok(csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(file))
+}
+
+/// Names forced by hand, either for a record id (and column)
+/// or for an exact name
+pub struct Overrides {
+    by_id: HashMap<(String, String), String>,
+    by_name: HashMap<String, String>,
+}
+impl Overrides {
+    pub fn new(file_path: &Path) -> Result<Self> {
+        let mut by_id = HashMap::new();
+        let mut by_name = HashMap::new();
+        for row in read_rows(file_path)? {
+            if !row.id.is_empty() {
+                by_id.insert((row.id, row.column), row.new_name);
+            } else if !row.old_name.is_empty() {
+                by_name.insert(row.old_name, row.new_name);
+            }
+        }
+        Ok(Overrides { by_id, by_name })
+    }
+
+    /// forced name of the record if any, looked up by id (for the column, then for any column)
+    /// then by the original name of the record
+    pub fn get(&self, id: Option<&str>, column: Option<&str>, name: &str) -> Option<&str> {
+        let by_id = id.and_then(|id| {
+            column
+                .and_then(|c| self.by_id.get(&(id.to_string(), c.to_string())))
+                .or_else(|| self.by_id.get(&(id.to_string(), String::new())))
+        });
+        by_id.or_else(|| self.by_name.get(name)).map(|s| s.as_str())
+    }
+}
@@ -2,6 +2,7 @@ use regex::{Regex, RegexBuilder};
 use utils;
 use std::fmt::Write;
 use errors::{Result, ResultExt};
+use log::Level;
 
 pub enum CaseSpecifier {
     Lower,
@@ -66,21 +67,61 @@ impl RegexReplace {
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    #[default]
+    Warning,
+    Error,
+}
+
+/// a match of a LogSuspicious regex
+#[derive(Debug, Clone, PartialEq)]
+pub struct Suspicion {
+    pub severity: Severity,
+    pub matched: String,
+    /// byte span of the match in the name
+    pub start: usize,
+    pub end: usize,
+    pub name: String,
+}
+
 pub struct LogSuspicious {
     regex: Regex,
+    severity: Severity,
+    suspicions: Vec<Suspicion>,
 }
 impl LogSuspicious {
-    pub fn new(regex: &str) -> Result<Self> {
+    pub fn new(regex: &str, severity: Severity) -> Result<Self> {
         Ok(LogSuspicious {
             regex: RegexBuilder::new(regex)
                 .case_insensitive(true)
                 .build()
                 .chain_err(|| format!("Problem building the Regex from {}", regex))?,
+            severity,
+            suspicions: vec![],
         })
     }
-    pub fn process(&self, name: &str) {
+    pub fn process(&mut self, name: &str) {
         for m in self.regex.find_iter(name) {
-            println!("Warning: suspicious match {} in name {}", m.as_str(), name);
+            let level = match self.severity {
+                Severity::Info => Level::Info,
+                Severity::Warning => Level::Warn,
+                Severity::Error => Level::Error,
+            };
+            log!(level, "suspicious match {} in name {}", m.as_str(), name);
+            self.suspicions.push(Suspicion {
+                severity: self.severity,
+                matched: m.as_str().to_string(),
+                start: m.start(),
+                end: m.end(),
+                name: name.to_string(),
+            });
         }
     }
+    /// matches found since last call
+    pub fn take_suspicions(&mut self) -> Vec<Suspicion> {
+        ::std::mem::take(&mut self.suspicions)
+    }
 }
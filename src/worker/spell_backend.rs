@@ -0,0 +1,129 @@
+use ispell;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::str::FromStr;
+use super::dictionary::Dictionary;
+use super::spell_cache::{self, Hash};
+use errors::{Result, ResultExt};
+
+/// a word of the checked text not found in dictionary
+#[derive(Debug, Clone, PartialEq)]
+pub struct Misspelling {
+    pub misspelled: String,
+    /// byte offset of the word in the checked text
+    pub position: usize,
+    pub suggestions: Vec<String>,
+}
+
+/// a spell-checker used by IspellCheck
+pub trait SpellBackend {
+    /// words of the text not found in dictionary, with suggestions
+    /// (the suggestions with the same normed form as the word are the ones used)
+    fn check(&mut self, text: &str) -> Result<Vec<Misspelling>>;
+
+    /// add a word to the dictionary for the time of the run
+    fn add_word(&mut self, word: &str) -> Result<()>;
+
+    /// hash of the dictionary files (to invalidate caches when they change)
+    fn dictionary_hash(&self) -> Result<Hash>;
+}
+
+/// the spell-checker used by IspellCheck
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BackendKind {
+    /// the dictionary is an aspell dictionary name
+    #[default]
+    Aspell,
+    /// the dictionary is the path of .aff/.dic files
+    Hunspell,
+    /// the dictionary is the path of a file with one word per line
+    WordList,
+}
+impl FromStr for BackendKind {
+    type Err = String;
+    fn from_str(s: &str) -> ::std::result::Result<Self, Self::Err> {
+        match s {
+            "aspell" => Ok(BackendKind::Aspell),
+            "hunspell" => Ok(BackendKind::Hunspell),
+            "word_list" => Ok(BackendKind::WordList),
+            _ => Err(format!("unknown backend {} (aspell, hunspell or word_list)", s)),
+        }
+    }
+}
+
+/// the spell-checker of the dictionary (the paths are relative to base_path)
+pub fn new_backend(
+    kind: BackendKind,
+    dictionary: &str,
+    base_path: &Path,
+) -> Result<Box<dyn SpellBackend>> {
+    Ok(match kind {
+        BackendKind::Aspell => {
+            Box::new(Aspell::new(dictionary).chain_err(|| "Could not create ispell manager")?)
+        }
+        BackendKind::Hunspell => Box::new(Dictionary::from_hunspell(&base_path.join(dictionary))?),
+        BackendKind::WordList => Box::new(Dictionary::from_word_list(&base_path.join(dictionary))?),
+    })
+}
+
+/// aspell, launched as a subprocess
+pub struct Aspell {
+    checker: ispell::SpellChecker,
+    dict: String,
+}
+impl Aspell {
+    pub fn new(dict: &str) -> Result<Self> {
+        Ok(Aspell {
+            checker: ispell::SpellLauncher::new()
+                .aspell()
+                .dictionary(dict)
+                .timeout(10_000)
+                .launch()?,
+            dict: dict.to_string(),
+        })
+    }
+}
+impl SpellBackend for Aspell {
+    fn check(&mut self, text: &str) -> Result<Vec<Misspelling>> {
+        let errors = self.checker
+            .check(text)
+            .chain_err(|| "Could not perform check using aspell")?;
+        Ok(errors
+            .into_iter()
+            .map(|e| Misspelling {
+                misspelled: e.misspelled,
+                position: e.position,
+                suggestions: e.suggestions,
+            })
+            .collect())
+    }
+
+    fn add_word(&mut self, word: &str) -> Result<()> {
+        self.checker.add_word(word)?;
+        Ok(())
+    }
+
+    // the files of the dictionary are the ones of aspell dict-dir starting with its name
+    fn dictionary_hash(&self) -> Result<Hash> {
+        let output = Command::new("aspell")
+            .args(["config", "dict-dir"])
+            .output()
+            .chain_err(|| "Could not find aspell dict-dir")?;
+        let dict_dir = String::from_utf8_lossy(&output.stdout).trim().to_string();
+        let mut files = vec![];
+        for entry in fs::read_dir(Path::new(&dict_dir))
+            .chain_err(|| format!("Could not read aspell dict-dir {}", dict_dir))?
+        {
+            let path = entry?.path();
+            let is_dict_file = path.file_name()
+                .is_some_and(|n| n.to_string_lossy().starts_with(&self.dict));
+            if is_dict_file && path.is_file() {
+                files.push(path);
+            }
+        }
+        files.sort();
+        spell_cache::hash_files(&files)
+    }
+}
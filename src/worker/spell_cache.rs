@@ -0,0 +1,518 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use super::spell_backend::{Misspelling, SpellBackend};
+use errors::{Result, ResultExt};
+
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0100_0000_01b3;
+
+/// FNV-1a hash, stable across runs and platforms
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hash(u64);
+impl Default for Hash {
+    fn default() -> Self {
+        Hash(FNV_OFFSET)
+    }
+}
+impl Hash {
+    pub fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= u64::from(*b);
+            self.0 = self.0.wrapping_mul(FNV_PRIME);
+        }
+    }
+
+    pub fn to_hex(&self) -> String {
+        format!("{:016x}", self.0)
+    }
+}
+
+/// hash of the contents of the files
+pub fn hash_files<P: AsRef<Path>>(paths: &[P]) -> Result<Hash> {
+    let mut hash = Hash::default();
+    let mut buf = vec![0; 64 * 1024];
+    for p in paths {
+        let p = p.as_ref();
+        let mut file = File::open(p).chain_err(|| format!("Could not read {}", p.display()))?;
+        loop {
+            let n = file.read(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            hash.write(&buf[..n]);
+        }
+    }
+    Ok(hash)
+}
+
+/// first line of a cache file, the entries are dropped if it differs
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+pub struct CacheKey {
+    pub dictionary: String,
+    pub dictionary_hash: String,
+    /// hash of the words added to the dictionary (or of the BANO files they come from)
+    pub words_hash: String,
+}
+
+impl CacheKey {
+    fn hash(&self) -> Hash {
+        let mut hash = Hash::default();
+        for field in &[&self.dictionary, &self.dictionary_hash, &self.words_hash] {
+            hash.write(field.as_bytes());
+            hash.write(b"\n");
+        }
+        hash
+    }
+}
+
+/// path of a cache file in the cache directory, named after the dictionary
+/// and the hash of the key (each configuration of the dictionary has its own file)
+pub fn cache_path(cache_dir: &Path, key: &CacheKey, kind: &str) -> PathBuf {
+    let name: String = Path::new(&key.dictionary)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default()
+        .chars()
+        .map(|c| if c.is_alphanumeric() { c } else { '_' })
+        .collect();
+    cache_dir.join(format!("{}.{}.{}.jsonl", name, key.hash().to_hex(), kind))
+}
+
+/// entries of a cache file (one json object per line after the key),
+/// None if the file doesn't exist, its key differs or it is corrupted
+pub fn read_entries<E: DeserializeOwned>(path: &Path, key: &CacheKey) -> Result<Option<Vec<E>>> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(_) => return Ok(None),
+    };
+    let mut lines = BufReader::new(file).lines();
+    let file_key: Option<CacheKey> = match lines.next() {
+        Some(l) => serde_json::from_str(&l?).ok(),
+        None => None,
+    };
+    if file_key.as_ref() != Some(key) {
+        info!("Cache {} is outdated", path.display());
+        return Ok(None);
+    }
+    let mut entries = vec![];
+    for l in lines {
+        match l.map(|l| serde_json::from_str(&l)) {
+            Ok(Ok(e)) => entries.push(e),
+            // a line may be truncated if a run was interrupted
+            _ => {
+                warn!("Cache {} is corrupted, it is rebuilt", path.display());
+                return Ok(None);
+            }
+        }
+    }
+    Ok(Some(entries))
+}
+
+/// key and entries of a file written by write_entries
+pub fn read_all<K: DeserializeOwned, E: DeserializeOwned>(path: &Path) -> Result<(K, Vec<E>)> {
+    let file = File::open(path).chain_err(|| format!("Could not open {}", path.display()))?;
+    let mut lines = BufReader::new(file).lines();
+    let key = match lines.next() {
+        Some(l) => serde_json::from_str(&l?)?,
+        None => bail!("{} is empty", path.display()),
+    };
+    let mut entries = vec![];
+    for l in lines {
+        entries.push(serde_json::from_str(&l?)?);
+    }
+    Ok((key, entries))
+}
+
+fn write_line<W: Write, T: Serialize>(w: &mut W, value: &T) -> Result<()> {
+    serde_json::to_writer(&mut *w, value)?;
+    writeln!(w)?;
+    Ok(())
+}
+
+fn create<K: Serialize>(path: &Path, key: &K) -> Result<BufWriter<File>> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .chain_err(|| format!("Could not create cache directory {}", dir.display()))?;
+    }
+    let file = File::create(path).chain_err(|| format!("Could not create cache {}", path.display()))?;
+    let mut w = BufWriter::new(file);
+    write_line(&mut w, key)?;
+    Ok(w)
+}
+
+/// write a whole cache file
+pub fn write_entries<K: Serialize, E: Serialize>(path: &Path, key: &K, entries: &[E]) -> Result<()> {
+    let mut w = create(path, key)?;
+    for e in entries {
+        write_line(&mut w, e)?;
+    }
+    w.flush()?;
+    Ok(())
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct CachedMisspelling {
+    misspelled: String,
+    /// byte offset in the word
+    position: usize,
+    suggestions: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WordEntry {
+    word: String,
+    // empty if the word is correct
+    misspellings: Vec<CachedMisspelling>,
+}
+
+#[derive(Default)]
+struct WordsStore {
+    words: HashMap<String, Vec<CachedMisspelling>>,
+    file: Option<BufWriter<File>>,
+}
+
+/// results of the spell-checker per word (separated by whitespace, the backend splitting them
+/// as it does for whole names, ex: at apostrophes), kept in memory for the run,
+/// and stored in a file if opened from one (new results are appended to the file)
+#[derive(Default)]
+pub struct WordsCache {
+    store: Arc<Mutex<WordsStore>>,
+    nb_hits: usize,
+    nb_misses: usize,
+}
+impl WordsCache {
+    pub fn new() -> Self {
+        WordsCache::default()
+    }
+
+    pub fn open(path: &Path, key: &CacheKey) -> Result<Self> {
+        let (entries, file) = match read_entries::<WordEntry>(path, key)? {
+            Some(entries) => {
+                let file = OpenOptions::new()
+                    .append(true)
+                    .open(path)
+                    .chain_err(|| format!("Could not open cache {}", path.display()))?;
+                (entries, BufWriter::new(file))
+            }
+            None => (vec![], create(path, key)?),
+        };
+        info!("{} words read from cache {}", entries.len(), path.display());
+        let store = WordsStore {
+            words: entries
+                .into_iter()
+                .map(|e| (e.word, e.misspellings))
+                .collect(),
+            file: Some(file),
+        };
+        Ok(WordsCache {
+            store: Arc::new(Mutex::new(store)),
+            ..WordsCache::default()
+        })
+    }
+
+    // a cache sharing the results (and the file) of this one
+    fn share(&self) -> Self {
+        WordsCache {
+            store: self.store.clone(),
+            ..WordsCache::default()
+        }
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, WordsStore>> {
+        self.store
+            .lock()
+            .map_err(|_| "A job stopped while using the spell-check cache".into())
+    }
+
+    /// (words found in cache, words checked by the spell-checker)
+    pub fn stats(&self) -> (usize, usize) {
+        (self.nb_hits, self.nb_misses)
+    }
+
+    /// misspelled words of the text, the words not in cache being checked
+    /// by the spell-checker (one by one)
+    pub fn check(&mut self, backend: &mut dyn SpellBackend, text: &str) -> Result<Vec<Misspelling>> {
+        let mut words = vec![];
+        let mut position = 0;
+        for w in text.split_whitespace() {
+            position += text[position..].find(w).unwrap_or(0);
+            if w.chars().any(char::is_alphabetic) {
+                words.push((position, w));
+            }
+            position += w.len();
+        }
+
+        let mut distinct: Vec<&str> = words.iter().map(|&(_, w)| w).collect();
+        distinct.sort();
+        distinct.dedup();
+        let missing: Vec<&str> = {
+            let store = self.lock()?;
+            distinct
+                .iter()
+                .cloned()
+                .filter(|w| !store.words.contains_key(*w))
+                .collect()
+        };
+        self.nb_misses += missing.len();
+        self.nb_hits += distinct.len() - missing.len();
+        for w in missing {
+            let misspellings: Vec<_> = backend
+                .check(w)?
+                .into_iter()
+                .map(|e| CachedMisspelling {
+                    misspelled: e.misspelled,
+                    position: e.position,
+                    suggestions: e.suggestions,
+                })
+                .collect();
+            let mut guard = self.lock()?;
+            let store = &mut *guard;
+            // the whole line is written at once, the file being locked
+            if let Some(ref mut file) = store.file {
+                write_line(
+                    file,
+                    &WordEntry {
+                        word: w.to_string(),
+                        misspellings: misspellings.clone(),
+                    },
+                ).chain_err(|| "Could not write into spell-check cache")?;
+            }
+            store.words.insert(w.to_string(), misspellings);
+        }
+
+        let store = self.lock()?;
+        Ok(words
+            .into_iter()
+            .flat_map(|(position, w)| {
+                store.words[w].iter().map(move |e| Misspelling {
+                    misspelled: e.misspelled.clone(),
+                    position: position + e.position,
+                    suggestions: e.suggestions.clone(),
+                })
+            })
+            .collect())
+    }
+}
+
+/// cache files opened by the processors built from a config, shared by all of them
+/// (ex: by the jobs of a pool), so that each file is read and written by a single owner
+#[derive(Clone, Default)]
+pub struct SharedCaches {
+    words: Arc<Mutex<HashMap<PathBuf, WordsCache>>>,
+    // words added to the dictionary, per path of their cache file
+    bano: Arc<Mutex<HashMap<PathBuf, Vec<String>>>>,
+    dictionary_hashes: Arc<Mutex<HashMap<String, Hash>>>,
+}
+impl SharedCaches {
+    /// the words cache of the file, opened the first time
+    pub fn words_cache(&self, path: &Path, key: &CacheKey) -> Result<WordsCache> {
+        let mut caches = self.words
+            .lock()
+            .map_err(|_| "A job stopped while opening the spell-check cache")?;
+        if let Some(cache) = caches.get(path) {
+            return Ok(cache.share());
+        }
+        let cache = WordsCache::open(path, key)?;
+        let shared = cache.share();
+        caches.insert(path.to_path_buf(), cache);
+        Ok(shared)
+    }
+
+    /// the words added to the dictionary kept in the file,
+    /// computed by compute the first time (the others waiting for it)
+    pub fn added_words<F>(&self, path: &Path, compute: F) -> Result<Vec<String>>
+    where
+        F: FnOnce() -> Result<Vec<String>>,
+    {
+        let mut caches = self.bano
+            .lock()
+            .map_err(|_| "A job stopped while computing the words added to the dictionary")?;
+        if let Some(words) = caches.get(path) {
+            return Ok(words.clone());
+        }
+        let words = compute()?;
+        caches.insert(path.to_path_buf(), words.clone());
+        Ok(words)
+    }
+
+    /// the hash of the dictionary, computed by compute the first time
+    pub fn dictionary_hash<F>(&self, dictionary: &str, compute: F) -> Result<Hash>
+    where
+        F: FnOnce() -> Result<Hash>,
+    {
+        let mut hashes = self.dictionary_hashes
+            .lock()
+            .map_err(|_| "A job stopped while computing the hash of the dictionary")?;
+        if let Some(hash) = hashes.get(dictionary) {
+            return Ok(*hash);
+        }
+        let hash = compute()?;
+        hashes.insert(dictionary.to_string(), hash);
+        Ok(hash)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use std::{env, process};
+    use errors::Result;
+    use worker::spell_backend::{Misspelling, SpellBackend};
+    use super::*;
+
+    // "ecole" is misspelled (as "école"), the checked texts being kept
+    #[derive(Default)]
+    struct Backend {
+        checked: Vec<String>,
+    }
+    impl SpellBackend for Backend {
+        fn check(&mut self, text: &str) -> Result<Vec<Misspelling>> {
+            self.checked.push(text.to_string());
+            Ok(text.find("ecole")
+                .map(|position| Misspelling {
+                    misspelled: "ecole".to_string(),
+                    position,
+                    suggestions: vec!["école".to_string()],
+                })
+                .into_iter()
+                .collect())
+        }
+
+        fn add_word(&mut self, _: &str) -> Result<()> {
+            Ok(())
+        }
+
+        fn dictionary_hash(&self) -> Result<Hash> {
+            Ok(Hash::default())
+        }
+    }
+
+    // a path in the temporary directory, without file
+    fn temp_path(name: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("ruspell-cache-{}-{}.jsonl", process::id(), name));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn key(dictionary: &str) -> CacheKey {
+        CacheKey {
+            dictionary: dictionary.to_string(),
+            dictionary_hash: Hash::default().to_hex(),
+            words_hash: Hash::default().to_hex(),
+        }
+    }
+
+    fn misspelled(errors: &[Misspelling]) -> Vec<(&str, usize)> {
+        errors
+            .iter()
+            .map(|e| (e.misspelled.as_str(), e.position))
+            .collect()
+    }
+
+    #[test]
+    fn words() {
+        let mut backend = Backend::default();
+        let mut cache = WordsCache::new();
+        let errors = cache.check(&mut backend, "l'ecole 12 d'aujourd'hui l'ecole").unwrap();
+        assert_eq!(misspelled(&errors), vec![("ecole", 2), ("ecole", 27)]);
+        assert_eq!(errors[0].suggestions, vec!["école"]);
+        // the words are checked whole, once
+        assert_eq!(backend.checked, vec!["d'aujourd'hui", "l'ecole"]);
+        assert_eq!(cache.stats(), (0, 2));
+        cache.check(&mut backend, "l'ecole").unwrap();
+        assert_eq!(cache.stats(), (1, 2));
+    }
+
+    #[test]
+    fn round_trip() {
+        let path = temp_path("round_trip");
+        write_entries(&path, &key("fr"), &["a", "b"]).unwrap();
+        let entries: Vec<String> = read_entries(&path, &key("fr")).unwrap().unwrap();
+        assert_eq!(entries, vec!["a", "b"]);
+
+        let mut backend = Backend::default();
+        {
+            let mut cache = WordsCache::open(&path, &key("fr")).unwrap();
+            cache.check(&mut backend, "rue de l'ecole").unwrap();
+        }
+        let mut cache = WordsCache::open(&path, &key("fr")).unwrap();
+        let errors = cache.check(&mut backend, "l'ecole de la rue").unwrap();
+        assert_eq!(misspelled(&errors), vec![("ecole", 2)]);
+        assert_eq!(backend.checked, vec!["de", "l'ecole", "rue", "la"]);
+        assert_eq!(cache.stats(), (3, 1));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn truncated() {
+        let path = temp_path("truncated");
+        let mut backend = Backend::default();
+        WordsCache::open(&path, &key("fr"))
+            .unwrap()
+            .check(&mut backend, "rue de l'ecole")
+            .unwrap();
+        // an interrupted run
+        let content = fs::read_to_string(&path).unwrap();
+        fs::write(&path, &content[..content.len() - 10]).unwrap();
+        assert!(read_entries::<WordEntry>(&path, &key("fr")).unwrap().is_none());
+
+        let mut cache = WordsCache::open(&path, &key("fr")).unwrap();
+        cache.check(&mut backend, "rue").unwrap();
+        assert_eq!(cache.stats(), (0, 1));
+        drop(cache);
+        let entries = read_entries::<WordEntry>(&path, &key("fr")).unwrap().unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].word, "rue");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn key_mismatch() {
+        let path = temp_path("key_mismatch");
+        assert!(read_entries::<String>(&path, &key("fr")).unwrap().is_none());
+        write_entries(&path, &key("fr"), &["a"]).unwrap();
+        assert!(read_entries::<String>(&path, &key("en")).unwrap().is_none());
+
+        let mut backend = Backend::default();
+        WordsCache::open(&path, &key("fr"))
+            .unwrap()
+            .check(&mut backend, "rue")
+            .unwrap();
+        let mut cache = WordsCache::open(&path, &key("en")).unwrap();
+        cache.check(&mut backend, "rue").unwrap();
+        assert_eq!(cache.stats(), (0, 1));
+        assert_eq!(backend.checked, vec!["rue", "rue"]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn shared() {
+        let path = temp_path("shared");
+        let caches = SharedCaches::default();
+        let mut first = caches.words_cache(&path, &key("fr")).unwrap();
+        let mut second = caches.words_cache(&path, &key("fr")).unwrap();
+        let mut backend = Backend::default();
+        first.check(&mut backend, "rue de l'ecole").unwrap();
+        let errors = second.check(&mut backend, "l'ecole").unwrap();
+        assert_eq!(misspelled(&errors), vec![("ecole", 2)]);
+        assert_eq!(backend.checked, vec!["de", "l'ecole", "rue"]);
+        assert_eq!(first.stats(), (0, 3));
+        assert_eq!(second.stats(), (1, 0));
+
+        // the results of both are written once in the file
+        second.check(&mut backend, "gare").unwrap();
+        drop((first, second));
+        drop(caches);
+        let entries = read_entries::<WordEntry>(&path, &key("fr")).unwrap().unwrap();
+        let words: Vec<_> = entries.iter().map(|e| e.word.as_str()).collect();
+        assert_eq!(words, vec!["de", "l'ecole", "rue", "gare"]);
+        fs::remove_file(&path).unwrap();
+    }
+}